@@ -0,0 +1,421 @@
+//! Storage of ignore files.
+//!
+//! Commands read, modify and remove ignore files (and the folder's policy,
+//! the global patterns and files deleted by `clean`) through a [`Backend`],
+//! selected with --backend. `Local` changes the files on disk, `Memory` keeps
+//! the changes in memory, so a command can be tried out without changing
+//! anything. Walking the folder itself (stats, lint, bench, clean) still
+//! reads the local file system.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{ErrorKind, Result, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::debug;
+
+use crate::normalize;
+
+pub trait Backend {
+    /// Contents of the file at `path`, `None` if it doesn't exist
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>>;
+
+    /// Replaces the file at `path` with `contents` atomically. A symlink at
+    /// `path` is kept and the file it points to is replaced
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()>;
+
+    /// Appends `contents` to the file at `path` with a single write,
+    /// creating the file if it doesn't exist
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Removes the file, or the directory with everything in it, at `path`
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Creates the directory `path` and its missing parents
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// `path` with symlinks and `.`/`..` resolved, fails if it doesn't exist
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
+}
+
+/// Files on the local file system
+pub struct Local {
+    /// Flush written files and their directories to disk before returning
+    pub fsync: bool,
+}
+
+impl Local {
+    /// Makes sure changes of the file at `path` and its directory survive a crash
+    fn sync(&self, path: &Path) -> Result<()> {
+        if !self.fsync {
+            return Ok(());
+        }
+        debug!("Syncing {} to disk", path.display());
+        File::open(path)?.sync_all()?;
+        // directories can't be opened on windows, NTFS journals renames anyway
+        #[cfg(unix)]
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Local {
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        debug!("Reading {}", path.display());
+        match std::fs::read(path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        // the rename would replace a symlink instead of the file it points to
+        let real = match path.canonicalize() {
+            Ok(real) => real,
+            Err(e) if e.kind() == ErrorKind::NotFound => path.to_path_buf(),
+            Err(e) => return Err(e),
+        };
+        let path = real.as_path();
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(path.file_name().ok_or(ErrorKind::InvalidInput)?);
+        tmp_name.push(".tmp");
+        let tmp = path.with_file_name(tmp_name);
+        debug!("Rewriting {} through {}", path.display(), tmp.display());
+        std::fs::write(&tmp, contents)
+            .and_then(|_| copy_metadata(path, &tmp))
+            // data has to be on disk before the rename is
+            .and_then(|_| match self.fsync {
+                true => File::open(&tmp)?.sync_all(),
+                false => Ok(()),
+            })
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .inspect_err(|_| {
+                let _ = std::fs::remove_file(&tmp);
+            })?;
+        self.sync(path)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        debug!("Appending {} bytes to {}", contents.len(), path.display());
+        File::options()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .write_all(contents)?;
+        self.sync(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        debug!("Moving {} to {}", from.display(), to.display());
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        debug!("Removing {}", path.display());
+        match std::fs::symlink_metadata(path)?.is_dir() {
+            true => std::fs::remove_dir_all(path),
+            false => std::fs::remove_file(path),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        path.canonicalize()
+    }
+}
+
+/// Changes kept in memory, nothing is written to disk. Files that weren't
+/// changed are read from disk, unless the backend is `empty`
+#[derive(Default)]
+pub struct Memory {
+    /// `None` for removed files
+    files: Mutex<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+    disk: Option<Local>,
+}
+
+impl Memory {
+    /// Backend on top of the local file system
+    pub fn on_disk() -> Self {
+        Self {
+            files: Mutex::default(),
+            disk: Some(Local { fsync: false }),
+        }
+    }
+
+    /// Backend with only `files`, as `(path, contents)`
+    #[cfg(test)]
+    pub fn with_files(files: &[(&str, &str)]) -> Self {
+        let memory = Self::default();
+        for (path, contents) in files {
+            memory.files().insert(
+                normalize(Path::new(path)),
+                Some(contents.as_bytes().to_vec()),
+            );
+        }
+        memory
+    }
+
+    /// Changed files with their new contents, `None` for removed ones
+    pub fn changes(&self) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+        self.files().clone().into_iter().collect()
+    }
+
+    fn files(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Option<Vec<u8>>>> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Changed or removed contents of `path`, `None` if it wasn't changed
+    fn changed(&self, path: &Path) -> Option<Option<Vec<u8>>> {
+        self.files().get(&normalize(path)).cloned()
+    }
+}
+
+impl Backend for Memory {
+    fn read(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+        match (self.changed(path), &self.disk) {
+            (Some(contents), _) => Ok(contents),
+            (None, Some(disk)) => disk.read(path),
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        debug!("Keeping new contents of {} in memory", path.display());
+        self.files()
+            .insert(normalize(path), Some(contents.to_vec()));
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let mut new = self.read(path)?.unwrap_or_default();
+        new.extend_from_slice(contents);
+        self.write(path, &new)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let contents = self.read(from)?.ok_or(ErrorKind::NotFound)?;
+        self.write(to, &contents)?;
+        self.remove(from)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        if !self.exists(path) {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let path = normalize(path);
+        let mut files = self.files();
+        // files inside of a removed directory are gone too
+        files.retain(|p, _| !p.starts_with(&path));
+        files.insert(path, None);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        let files = self.files();
+        let removed = files
+            .iter()
+            .any(|(p, c)| c.is_none() && path.starts_with(p));
+        // directories exist as long as there are files inside of them
+        let dir = files
+            .iter()
+            .any(|(p, c)| c.is_some() && p.starts_with(&path));
+        drop(files);
+        match (self.changed(&path), &self.disk) {
+            (Some(contents), _) => contents.is_some(),
+            _ if dir => true,
+            _ if removed => false,
+            (None, Some(disk)) => disk.exists(&path),
+            (None, None) => false,
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match (self.changed(path), &self.disk) {
+            (Some(contents), _) => contents.is_some(),
+            (None, Some(disk)) => self.exists(path) && disk.is_file(path),
+            (None, None) => false,
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        match (self.changed(path), &self.disk) {
+            (Some(Some(_)), _) => Ok(normalize(path)),
+            (None, Some(disk)) if self.exists(path) => disk.canonicalize(path),
+            _ => Err(ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// Gives `copy` the owner, permissions and extended attributes of `original`,
+/// so rewriting a file doesn't reset them to the defaults of a new one. The
+/// owner and attributes are kept where possible: only root can give files away,
+/// and not every file system has extended attributes
+fn copy_metadata(original: &Path, copy: &Path) -> Result<()> {
+    let meta = match std::fs::metadata(original) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Err(e) = std::os::unix::fs::chown(copy, Some(meta.uid()), Some(meta.gid())) {
+            debug!("Can't keep the owner of {}: {e}", original.display());
+        }
+    }
+    #[cfg(target_os = "linux")]
+    copy_xattrs(original, copy);
+    std::fs::set_permissions(copy, meta.permissions())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(original: &Path, copy: &Path) {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt, ptr::null_mut};
+    let c_path = |p: &Path| CString::new(p.as_os_str().as_bytes());
+    let (Ok(from), Ok(to)) = (c_path(original), c_path(copy)) else {
+        return;
+    };
+    // SAFETY: paths are valid C strings, buffers are as long as the sizes passed with them
+    let len = unsafe { libc::listxattr(from.as_ptr(), null_mut(), 0) };
+    if len <= 0 {
+        return;
+    }
+    let mut names = vec![0u8; len as usize];
+    let len = unsafe { libc::listxattr(from.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if len <= 0 {
+        return;
+    }
+    names.truncate(len as usize);
+    for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+        let Ok(name) = CString::new(name) else {
+            continue;
+        };
+        let len = unsafe { libc::getxattr(from.as_ptr(), name.as_ptr(), null_mut(), 0) };
+        if len < 0 {
+            continue;
+        }
+        let mut value = vec![0u8; len as usize];
+        let len = unsafe {
+            libc::getxattr(
+                from.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if len < 0 {
+            continue;
+        }
+        let res = unsafe {
+            libc::setxattr(
+                to.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                len as usize,
+                0,
+            )
+        };
+        if res != 0 {
+            debug!(
+                "Can't copy extended attribute {name:?} of {}: {}",
+                original.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_reads_what_was_written() {
+        let memory = Memory::default();
+        assert_eq!(memory.read(Path::new("/f/.stignore")).unwrap(), None);
+        memory.write(Path::new("/f/.stignore"), b"a\n").unwrap();
+        memory.append(Path::new("/f/./.stignore"), b"b\n").unwrap();
+        assert_eq!(
+            memory.read(Path::new("/f/sub/../.stignore")).unwrap(),
+            Some(b"a\nb\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn memory_renames() {
+        let memory = Memory::with_files(&[("/f/.stignore", "a")]);
+        memory
+            .rename(Path::new("/f/.stignore"), Path::new("/f/.stignore.corrupt"))
+            .unwrap();
+        assert!(!memory.exists(Path::new("/f/.stignore")));
+        assert!(memory.exists(Path::new("/f/.stignore.corrupt")));
+        assert!(memory
+            .rename(Path::new("/f/.stignore"), Path::new("/f/x"))
+            .is_err());
+    }
+
+    #[test]
+    fn memory_removes_directories() {
+        let memory = Memory::with_files(&[("/f/dir/a", "a"), ("/f/dir/sub/b", "b"), ("/f/c", "c")]);
+        memory.remove(Path::new("/f/dir")).unwrap();
+        assert!(!memory.exists(Path::new("/f/dir/sub/b")));
+        assert!(memory.exists(Path::new("/f/c")));
+        assert!(memory.remove(Path::new("/f/dir")).is_err());
+        memory.write(Path::new("/f/dir/a"), b"new").unwrap();
+        assert!(memory.is_file(Path::new("/f/dir/a")));
+    }
+
+    #[test]
+    fn memory_on_disk_never_writes() {
+        let dir = std::env::temp_dir().join(format!("stignore-memory-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(".stignore");
+        std::fs::write(&file, "a\n").unwrap();
+        let memory = Memory::on_disk();
+        memory.append(&file, b"b\n").unwrap();
+        assert_eq!(memory.read(&file).unwrap(), Some(b"a\nb\n".to_vec()));
+        memory.remove(&dir).unwrap();
+        assert!(!memory.exists(&file));
+        assert_eq!(std::fs::read(&file).unwrap(), b"a\n");
+        assert_eq!(memory.changes().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn memory_canonicalizes_existing_files() {
+        let memory = Memory::with_files(&[("/f/.stignore", "")]);
+        assert_eq!(
+            memory.canonicalize(Path::new("/f/x/../.stignore")).unwrap(),
+            PathBuf::from("/f/.stignore")
+        );
+        assert!(memory.canonicalize(Path::new("/f/missing")).is_err());
+    }
+}
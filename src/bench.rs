@@ -0,0 +1,191 @@
+//! Measuring how fast patterns are matched, to find the ones that slow
+//! syncthing's scans down.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::matcher::{self, Matcher};
+
+/// Rule is slow if matching it takes this many times longer than the median rule
+const SLOW_FACTOR: u32 = 10;
+/// Faster rules are never reported as slow, the difference is just noise
+const MIN_SLOW: Duration = Duration::from_nanos(200);
+/// Syncthing's glob matching backtracks on each wildcard, so patterns with
+/// more of them get expensive on deep paths
+const MAX_WILDCARDS: usize = 4;
+
+const WORDS: [&str; 16] = [
+    "src",
+    "docs",
+    "build",
+    "target",
+    "node_modules",
+    "photos",
+    "2022",
+    "backup",
+    "music",
+    "Projects",
+    "cache",
+    "tmp",
+    "notes",
+    "lib",
+    "assets",
+    "vendor",
+];
+const EXTENSIONS: [&str; 8] = [
+    "",
+    ".txt",
+    ".jpg",
+    ".rs",
+    ".tmp",
+    ".log",
+    ".json",
+    ".DS_Store",
+];
+
+/// Generates `n` pseudo-random paths, the same ones on every run
+pub fn synthetic_paths(n: usize) -> Vec<String> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = |bound: usize| {
+        // xorshift64
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+    (0..n)
+        .map(|_| {
+            let depth = 1 + next(8);
+            let mut path = (0..depth)
+                .map(|_| WORDS[next(WORDS.len())])
+                .collect::<Vec<_>>()
+                .join("/");
+            path.push_str(EXTENSIONS[next(EXTENSIONS.len())]);
+            path
+        })
+        .collect()
+}
+
+/// Up to `n` paths of files and directories of the folder `root`, without
+/// internal files and custom folder `markers`
+pub fn folder_paths(root: &Path, n: usize, markers: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut dirs = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, rel)) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if paths.len() >= n {
+                return paths;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if rel.is_empty() {
+                name
+            } else {
+                format!("{rel}/{name}")
+            };
+            if matcher::is_internal(&path, markers) {
+                continue;
+            }
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                dirs.push((entry.path(), path.clone()));
+            }
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Matching time of a single rule
+pub struct Timing {
+    pub pattern: String,
+    pub per_path: Duration,
+    /// Why the rule is considered slow
+    pub slow: Option<String>,
+}
+
+pub struct Results {
+    pub paths: usize,
+    /// Time it took to check all paths against the whole matcher
+    pub total: Duration,
+    pub rules: Vec<Timing>,
+}
+
+impl Results {
+    pub fn slow(&self) -> impl Iterator<Item = &Timing> {
+        self.rules.iter().filter(|t| t.slow.is_some())
+    }
+}
+
+impl std::fmt::Display for Results {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let per_second = self.paths as f64 / self.total.as_secs_f64().max(f64::EPSILON);
+        write!(
+            f,
+            "{} rules, {} paths: {:.2?} ({:.0} paths/s)",
+            self.rules.len(),
+            self.paths,
+            self.total,
+            per_second
+        )
+    }
+}
+
+/// Matches `paths` against `matcher` and against each of its rules separately
+pub fn run(matcher: &Matcher, paths: &[String]) -> Results {
+    let start = Instant::now();
+    for path in paths {
+        std::hint::black_box(matcher.is_ignored(path));
+    }
+    let total = start.elapsed();
+
+    let mut rules: Vec<_> = matcher
+        .rules()
+        .iter()
+        .map(|rule| {
+            let start = Instant::now();
+            for path in paths {
+                std::hint::black_box(rule.matches(path));
+            }
+            Timing {
+                pattern: rule.line.clone(),
+                per_path: start.elapsed() / paths.len().max(1) as u32,
+                slow: None,
+            }
+        })
+        .collect();
+
+    let mut sorted: Vec<_> = rules.iter().map(|t| t.per_path).collect();
+    sorted.sort();
+    let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+    for timing in &mut rules {
+        let wildcards = wildcards(&timing.pattern);
+        if timing.per_path > MIN_SLOW && timing.per_path > median * SLOW_FACTOR {
+            timing.slow = Some(format!(
+                "{}x slower than the median rule",
+                timing.per_path.as_nanos() / median.as_nanos().max(1)
+            ));
+        } else if wildcards > MAX_WILDCARDS {
+            timing.slow = Some(format!(
+                "{wildcards} wildcards, syncthing matches such patterns slowly on deep paths"
+            ));
+        }
+    }
+    Results {
+        paths: paths.len(),
+        total,
+        rules,
+    }
+}
+
+/// Number of `*` and `**` in `pattern`
+fn wildcards(pattern: &str) -> usize {
+    pattern
+        .split(|c| c != '*')
+        .filter(|run| !run.is_empty())
+        .count()
+}
@@ -0,0 +1,175 @@
+//! ANSI colors and hyperlinks for terminal output
+
+use std::{io::IsTerminal, path::Path};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+const MAGENTA: &str = "35";
+const CYAN: &str = "36";
+
+/// Colors for text written to one of the standard streams
+#[derive(Copy, Clone, Debug)]
+pub struct Palette {
+    enabled: bool,
+    hyperlinks: bool,
+}
+
+impl Palette {
+    pub fn stdout(choice: ColorChoice) -> Self {
+        Self::new(choice, std::io::stdout().is_terminal())
+    }
+
+    pub fn stderr(choice: ColorChoice) -> Self {
+        Self::new(choice, std::io::stderr().is_terminal())
+    }
+
+    /// `auto` enables colors only for terminals, unless $NO_COLOR is set
+    /// (https://no-color.org) or $TERM is `dumb`
+    fn new(choice: ColorChoice, terminal: bool) -> Self {
+        let enabled = match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                terminal
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                    && std::env::var_os("TERM").is_none_or(|t| t != "dumb")
+            }
+        };
+        Self {
+            enabled,
+            hyperlinks: enabled && supports_hyperlinks(),
+        }
+    }
+
+    /// `text` as a clickable link to the local file at `path`, if the
+    /// terminal supports OSC 8 hyperlinks
+    pub fn link(&self, text: &str, path: &Path) -> String {
+        if !self.hyperlinks {
+            return text.to_string();
+        }
+        format!("\x1b]8;;{}\x1b\\{text}\x1b]8;;\x1b\\", file_url(path))
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.enabled && !text.is_empty() {
+            format!("\x1b[{style}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn note(&self, text: &str) -> String {
+        self.paint(&format!("{BOLD};{YELLOW}"), text)
+    }
+
+    pub fn error(&self, text: &str) -> String {
+        self.paint(&format!("{BOLD};{RED}"), text)
+    }
+
+    pub fn location(&self, text: &str) -> String {
+        self.paint(BOLD, text)
+    }
+
+    pub fn removed(&self, text: &str) -> String {
+        self.paint(RED, text)
+    }
+
+    pub fn added(&self, text: &str) -> String {
+        self.paint(GREEN, text)
+    }
+
+    /// Colors processed patterns: modifiers, the prepended `prefix` and the
+    /// pattern supplied by the user are shown in different colors
+    pub fn patterns(&self, patterns: &str, prefix: Option<&str>) -> String {
+        if !self.enabled {
+            return patterns.to_string();
+        }
+        let mut out = String::new();
+        for line in patterns.split_inclusive('\n') {
+            let (line, ending) = match line.find(['\r', '\n']) {
+                Some(end) => line.split_at(end),
+                None => (line, ""),
+            };
+            out.push_str(&self.pattern_line(line, prefix));
+            out.push_str(ending);
+        }
+        out
+    }
+
+    fn pattern_line(&self, line: &str, prefix: Option<&str>) -> String {
+        if line.starts_with("//") {
+            return self.paint(DIM, line);
+        }
+        let (modifiers, pattern) = match line.strip_prefix("#include ") {
+            Some(rest) => ("#include ", rest),
+            None => crate::syntax::split_modifiers(line),
+        };
+        let (prefix, pattern) = match prefix {
+            Some(prefix) if pattern.starts_with(prefix) => pattern.split_at(prefix.len()),
+            _ => ("", pattern),
+        };
+        format!(
+            "{}{}{}",
+            self.paint(MAGENTA, modifiers),
+            self.paint(CYAN, prefix),
+            self.paint(BOLD, pattern)
+        )
+    }
+}
+
+/// Does the terminal show OSC 8 hyperlinks (and not garbage instead of them).
+/// $FORCE_HYPERLINK overrides the detection
+fn supports_hyperlinks() -> bool {
+    let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+    if let Some(force) = var("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    let term = var("TERM").unwrap_or_default();
+    matches!(
+        program.as_str(),
+        "iTerm.app" | "WezTerm" | "vscode" | "ghostty"
+    ) || matches!(
+        term.as_str(),
+        "xterm-kitty" | "alacritty" | "foot" | "xterm-ghostty"
+    ) || var("VTE_VERSION").and_then(|v| v.parse::<u32>().ok()) >= Some(5000)
+        || ["WT_SESSION", "KONSOLE_VERSION", "DOMTERM"]
+            .iter()
+            .any(|name| var(name).is_some())
+}
+
+/// `file://` URL of the local `path`
+fn file_url(path: &Path) -> String {
+    let path = crate::winpath::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = path.to_string_lossy().replace('\\', "/");
+    // Windows paths start with a drive letter, UNC ones with `//server`
+    let mut url = String::from(if path.starts_with("//") {
+        "file:"
+    } else if path.starts_with('/') {
+        "file://"
+    } else {
+        "file:///"
+    });
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~:".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    url
+}
@@ -0,0 +1,172 @@
+//! Defaults for command line options, read from configuration files.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::{color::ColorChoice, BackendKind, Backup, NonInteractive, Target, UnicodeForm};
+
+/// Name of the per-folder configuration file, placed next to `.stfolder`
+pub const FOLDER_CONFIG: &str = ".stignore.conf";
+
+/// Values of options that weren't passed on the command line.
+/// Missing values keep the built-in defaults.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub target: Option<Target>,
+    pub absolute: Option<bool>,
+    pub preview: Option<bool>,
+    pub non_interactive: Option<NonInteractive>,
+    pub color: Option<ColorChoice>,
+    pub pager: Option<bool>,
+    pub auto_case_insensitive: Option<bool>,
+    pub unicode_form: Option<UnicodeForm>,
+    pub provenance: Option<bool>,
+    pub fsync: Option<bool>,
+    pub backend: Option<BackendKind>,
+    pub backup: Option<Backup>,
+    /// The first route whose glob matches a pattern picks the file it's added to
+    pub routes: Option<Vec<Route>>,
+    /// `[remotes.NAME]` sections, syncthing instances of other devices
+    pub remotes: Option<BTreeMap<String, Remote>>,
+    /// `[api]` section, GUI of the local syncthing to use instead of the one
+    /// from its config.xml
+    pub api: Option<Remote>,
+    /// `[profile.NAME]` sections, selected with --profile
+    pub profile: BTreeMap<String, Config>,
+}
+
+/// Ignore file that patterns matching a glob are added to
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Glob in the ignore file syntax, matched against patterns being added
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Ignore file, relative to the folder root
+    pub file: PathBuf,
+}
+
+/// GUI of a syncthing instance: of another device, compared against by the
+/// diff command, or of this one
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Remote {
+    /// Address of the GUI, e.g. `http://desktop.lan:8384`
+    pub url: String,
+    /// API key from the GUI settings of that device
+    pub api_key: String,
+}
+
+impl Config {
+    /// Reads user's config.toml, overridden by `.stignore.conf` of `folder`.
+    ///
+    /// Values from `profile` section override the rest of the file.
+    pub fn load(folder: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let mut files = Vec::new();
+        if let Some(path) = user_config() {
+            files.push(Self::read(&path)?);
+        }
+        if let Some(folder) = folder {
+            files.push(Self::read(&folder.join(FOLDER_CONFIG))?);
+        }
+
+        let mut config = Self::default();
+        let mut profile_found = false;
+        for mut file in files {
+            let selected = profile.and_then(|p| file.profile.remove(p));
+            profile_found |= selected.is_some();
+            config = config.or(file);
+            if let Some(selected) = selected {
+                config = config.or(selected);
+            }
+        }
+        if let (Some(profile), false) = (profile, profile_found) {
+            bail!("Profile {profile:?} isn't defined in config files");
+        }
+        Ok(config)
+    }
+
+    /// Missing file is the same as an empty one
+    fn read(path: &Path) -> Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Can't read {}", path.display())),
+        };
+        debug!("Loaded config {}", path.display());
+        toml::from_str(&contents).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    /// Values of `other`, falling back to `self`
+    fn or(self, other: Self) -> Self {
+        Self {
+            target: other.target.or(self.target),
+            absolute: other.absolute.or(self.absolute),
+            preview: other.preview.or(self.preview),
+            non_interactive: other.non_interactive.or(self.non_interactive),
+            color: other.color.or(self.color),
+            pager: other.pager.or(self.pager),
+            auto_case_insensitive: other.auto_case_insensitive.or(self.auto_case_insensitive),
+            unicode_form: other.unicode_form.or(self.unicode_form),
+            provenance: other.provenance.or(self.provenance),
+            fsync: other.fsync.or(self.fsync),
+            backend: other.backend.or(self.backend),
+            backup: other.backup.or(self.backup),
+            routes: other.routes.or(self.routes),
+            remotes: match (self.remotes, other.remotes) {
+                (Some(mut remotes), Some(other)) => {
+                    remotes.extend(other);
+                    Some(remotes)
+                }
+                (remotes, other) => other.or(remotes),
+            },
+            api: other.api.or(self.api),
+            profile: BTreeMap::new(),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/stignore`
+pub fn dir() -> Result<PathBuf> {
+    Ok(crate::xdg_dir("XDG_CONFIG_HOME", ".config")?.join("stignore"))
+}
+
+/// `$XDG_CONFIG_HOME/stignore/config.toml`
+pub fn user_config() -> Option<PathBuf> {
+    dir().ok().map(|dir| dir.join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_add_remotes_and_override_the_api() {
+        let mut file: Config = toml::from_str(
+            r#"
+            [remotes.desktop]
+            url = "http://desktop.lan:8384"
+            api-key = "a"
+            [profile.work.remotes.server]
+            url = "http://server:8384"
+            api-key = "b"
+            [profile.work.api]
+            url = "http://127.0.0.1:8385"
+            api-key = "c"
+            "#,
+        )
+        .unwrap();
+        let work = file.profile.remove("work").unwrap();
+        let config = Config::default().or(file).or(work);
+        let remotes = config.remotes.unwrap();
+        assert_eq!(remotes.keys().collect::<Vec<_>>(), ["desktop", "server"]);
+        assert_eq!(config.api.unwrap().api_key, "c");
+    }
+}
@@ -0,0 +1,130 @@
+//! Detection of ignore files damaged by disk errors or encoding accidents.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::{backend::Backend, Failure};
+
+/// Appended to the name of a corrupt file when it is moved out of the way
+pub const SUFFIX: &str = ".corrupt";
+
+/// Byte order mark that Notepad and some other editors start UTF-8 files with
+pub const BOM: &str = "\u{feff}";
+
+/// First byte that can't be a part of a text ignore file
+#[derive(Debug)]
+pub struct Damage {
+    pub offset: usize,
+    pub byte: u8,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for Damage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (0x{:02x}) at byte offset {}",
+            self.reason, self.byte, self.offset
+        )
+    }
+}
+
+/// Damage of `contents`, if they don't look like a text file
+pub fn find(contents: &[u8]) -> Option<Damage> {
+    let control = contents
+        .iter()
+        .position(|&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c))
+        .map(|offset| Damage {
+            offset,
+            byte: contents[offset],
+            reason: if contents[offset] == 0 {
+                "null byte"
+            } else {
+                "control character"
+            },
+        });
+    let invalid = std::str::from_utf8(contents).err().map(|e| Damage {
+        offset: e.valid_up_to(),
+        byte: contents[e.valid_up_to()],
+        reason: "invalid UTF-8",
+    });
+    match (control, invalid) {
+        (Some(c), Some(i)) => Some(if c.offset < i.offset { c } else { i }),
+        (c, i) => c.or(i),
+    }
+}
+
+/// Do `contents` look like UTF-16 text: start with its byte order mark or
+/// have every other byte of the beginning null
+fn is_utf16(contents: &[u8]) -> bool {
+    if contents.starts_with(&[0xff, 0xfe]) || contents.starts_with(&[0xfe, 0xff]) {
+        return true;
+    }
+    let start = &contents[..contents.len().min(64)];
+    start.len() >= 4
+        && (start.iter().step_by(2).all(|&b| b == 0)
+            || start.iter().skip(1).step_by(2).all(|&b| b == 0))
+}
+
+/// `contents` without the UTF-8 byte order mark
+pub fn strip_bom(contents: String) -> String {
+    match contents.strip_prefix(BOM) {
+        Some(rest) => rest.to_string(),
+        None => contents,
+    }
+}
+
+/// `contents` of the ignore file at `path` as text without the byte order mark,
+/// fails if they are UTF-16 or look like binary garbage
+pub fn decode(path: &Path, contents: Vec<u8>) -> Result<String> {
+    if is_utf16(&contents) {
+        bail!(
+            "{} is encoded as UTF-16, but syncthing only reads UTF-8 ignore files. \
+            Save it as UTF-8 (e.g. in Notepad: Save As, Encoding: UTF-8) and try again",
+            path.display()
+        );
+    }
+    if let Some(damage) = find(&contents) {
+        bail!(Failure::Corrupt {
+            path: path.to_path_buf(),
+            damage,
+        });
+    }
+    Ok(strip_bom(
+        String::from_utf8(contents).expect("checked by find"),
+    ))
+}
+
+/// Contents of the ignore file at `path`, fails if it doesn't exist or looks
+/// like binary garbage
+pub fn read(backend: &dyn Backend, path: &Path) -> Result<String> {
+    let contents = backend
+        .read(path)
+        .and_then(|c| c.ok_or_else(|| std::io::ErrorKind::NotFound.into()))
+        .with_context(|| format!("Can't read {}", path.display()))?;
+    decode(path, contents)
+}
+
+/// Free `<name>.corrupt[.N]` name for a backup of the file at `path`
+pub fn backup_path(backend: &dyn Backend, path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(SUFFIX);
+    let mut n = 1;
+    while backend.exists(Path::new(&backup)) {
+        backup = path.as_os_str().to_owned();
+        backup.push(format!("{SUFFIX}.{n}"));
+        n += 1;
+    }
+    PathBuf::from(backup)
+}
+
+/// Moves the file at `path` to `backup`, so a fresh one can be started
+pub fn quarantine(backend: &dyn Backend, path: &Path, backup: &Path) -> Result<()> {
+    backend
+        .rename(path, backup)
+        .with_context(|| format!("Can't move {} to {}", path.display(), backup.display()))?;
+    info!("Moved {} to {}", path.display(), backup.display());
+    Ok(())
+}
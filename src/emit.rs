@@ -0,0 +1,32 @@
+//! Shell scripts that reproduce planned changes on another machine.
+
+use std::path::Path;
+
+/// `text` quoted for POSIX shells
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// POSIX shell script that appends `patterns` to `target` inside of `folder`.
+/// Patterns that are already present are skipped, so it can be run repeatedly
+pub fn append_script(folder: &Path, target: &Path, patterns: &str) -> String {
+    let file = target.strip_prefix(folder).unwrap_or(target);
+    let mut script = format!(
+        "#!/bin/sh\n\
+        # Generated by `stignore --emit-commands`, safe to run multiple times\n\
+        set -e\n\
+        cd -- {}\n\
+        f={}\n\
+        # make sure that the first pattern isn't glued to the last line\n\
+        if [ -s \"$f\" ] && [ -n \"$(tail -c 1 \"$f\")\" ]; then echo >> \"$f\"; fi\n",
+        quote(&folder.display().to_string()),
+        quote(&file.display().to_string())
+    );
+    for pattern in patterns.lines().map(str::trim).filter(|p| !p.is_empty()) {
+        let pattern = quote(pattern);
+        script.push_str(&format!(
+            "grep -qxF -- {pattern} \"$f\" 2>/dev/null || printf '%s\\n' {pattern} >> \"$f\"\n"
+        ));
+    }
+    script
+}
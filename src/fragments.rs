@@ -0,0 +1,46 @@
+//! `.stignore.d/*.stignore` fragments, included from a generated section of `.stignore`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+/// Directory with fragments, next to `.stfolder`
+pub const DIR: &str = ".stignore.d";
+
+/// Section of `.stignore` with `#include`s of the fragments
+pub const SECTION: &str = ".stignore.d";
+
+/// Path of fragment `name` relative to the folder root, `.stignore` extension is optional
+pub fn path(name: &str) -> Result<PathBuf> {
+    let name = name.strip_suffix(".stignore").unwrap_or(name);
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("Invalid fragment name {name:?}");
+    }
+    Ok(Path::new(DIR).join(format!("{name}.stignore")))
+}
+
+/// `#include` line for the fragment at `path`
+pub fn include(path: &Path) -> String {
+    // syncthing accepts forward slashes on every OS
+    format!("#include {}", path.display().to_string().replace('\\', "/"))
+}
+
+/// Fragments of the folder `root` in the order they are included: sorted by name
+pub fn list(root: &Path) -> Result<Vec<PathBuf>> {
+    let dir = root.join(DIR);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Can't read {}", dir.display())),
+    };
+    let mut fragments = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Can't read {}", dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".stignore") && !name.starts_with('.') && entry.path().is_file() {
+            fragments.push(Path::new(DIR).join(name));
+        }
+    }
+    fragments.sort();
+    Ok(fragments)
+}
@@ -0,0 +1,111 @@
+//! Per-user patterns, shared by all folders.
+//!
+//! Syncthing can't include files from outside of the folder, so every folder
+//! gets a generated copy of the patterns, which is included from its `.stignore`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::{
+    append, backend::Backend, config, enforce_policy, read_ignore_file, rewrite, LINE_ENDING,
+};
+
+/// Name of the generated copy of global patterns inside of a folder
+pub const COPY: &str = ".stignore_global";
+/// First line of files generated by stignore starts with this
+pub const GENERATED: &str = "// Generated from ";
+
+/// `$XDG_CONFIG_HOME/stignore/global.stignore`
+pub fn patterns_path() -> Result<PathBuf> {
+    Ok(config::dir()?.join("global.stignore"))
+}
+
+/// List of folders that include global patterns, one path per line
+fn folders_path() -> Result<PathBuf> {
+    Ok(config::dir()?.join("folders"))
+}
+
+/// Folders that include global patterns
+pub fn folders(backend: &dyn Backend) -> Result<Vec<PathBuf>> {
+    let path = folders_path()?;
+    let contents = read_ignore_file(backend, &path)?.unwrap_or_default();
+    Ok(contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Remembers `folder`, so it is updated when global patterns change
+pub fn register(backend: &dyn Backend, folder: &Path) -> Result<()> {
+    if folders(backend)?.iter().any(|f| f == folder) {
+        return Ok(());
+    }
+    let path = folders_path()?;
+    create_config_dir(backend)?;
+    let mut line = folder.display().to_string();
+    line.push_str(LINE_ENDING);
+    append(backend, &path, &line).with_context(|| format!("Can't write {}", path.display()))?;
+    info!("Registered {} in {}", folder.display(), path.display());
+    Ok(())
+}
+
+/// Appends `patterns` to the global ignore file
+pub fn add(backend: &dyn Backend, patterns: &str) -> Result<()> {
+    let path = patterns_path()?;
+    create_config_dir(backend)?;
+    append(backend, &path, patterns).with_context(|| format!("Can't append to {}", path.display()))
+}
+
+/// Updates the copy of global patterns in `folder` and makes sure that
+/// `.stignore` includes and ignores it. Returns whether anything was changed
+pub fn sync(backend: &dyn Backend, folder: &Path) -> Result<bool> {
+    let source = patterns_path()?;
+    let patterns = read_ignore_file(backend, &source)?.unwrap_or_default();
+    let generated = format!(
+        "{GENERATED}{} by `stignore global sync`, changes will be overwritten{LINE_ENDING}{patterns}",
+        source.display()
+    );
+
+    let mut changed = false;
+    let copy = folder.join(COPY);
+    let old = backend.read(&copy).ok().flatten();
+    if old.as_deref() != Some(generated.as_bytes()) {
+        let old = String::from_utf8_lossy(old.as_deref().unwrap_or_default());
+        enforce_policy(backend, folder, &copy, &old, &generated)?;
+        rewrite(backend, &copy, &generated)?;
+        changed = true;
+    }
+
+    // the copy is generated on every device, other devices shouldn't get it
+    let stignore = folder.join(".stignore");
+    let lines = [format!("/{COPY}"), format!("#include {COPY}")];
+    let contents = read_ignore_file(backend, &stignore)?.unwrap_or_default();
+    let missing: String = lines
+        .iter()
+        .filter(|line| !contents.lines().any(|l| l.trim() == line.as_str()))
+        .map(|line| format!("{line}{LINE_ENDING}"))
+        .collect();
+    if !missing.is_empty() {
+        enforce_policy(
+            backend,
+            folder,
+            &stignore,
+            &contents,
+            &format!("{contents}\n{missing}"),
+        )?;
+        append(backend, &stignore, &missing)
+            .with_context(|| format!("Can't append to {}", stignore.display()))?;
+        changed = true;
+    }
+    Ok(changed)
+}
+
+fn create_config_dir(backend: &dyn Backend) -> Result<()> {
+    let dir = config::dir()?;
+    backend
+        .create_dir_all(&dir)
+        .with_context(|| format!("Can't create {}", dir.display()))
+}
@@ -0,0 +1,119 @@
+//! Ignore files that are included by no one and `#include`s of missing files.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::{backend::Backend, config, corrupt, global, matcher, policy};
+
+/// `#include` of a file that doesn't exist
+pub struct Dangling {
+    pub file: PathBuf,
+    /// 1-based
+    pub line: usize,
+    pub text: String,
+}
+
+impl std::fmt::Display for Dangling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.text)
+    }
+}
+
+/// `#include`s of missing files in ignore `files`
+pub fn dangling(backend: &dyn Backend, files: &[PathBuf]) -> Result<Vec<Dangling>> {
+    let mut dangling = Vec::new();
+    for file in files {
+        let contents = corrupt::read(backend, file)?;
+        let dir = file.parent().unwrap_or(Path::new("."));
+        for (n, line) in contents.lines().enumerate() {
+            let Some(include) = line.trim().strip_prefix("#include") else {
+                continue;
+            };
+            let include = include.trim();
+            if !include.is_empty() && !backend.exists(&dir.join(include)) {
+                dangling.push(Dangling {
+                    file: file.clone(),
+                    line: n + 1,
+                    text: line.trim().to_string(),
+                });
+            }
+        }
+    }
+    Ok(dangling)
+}
+
+/// Does the name look like an ignore file: `.stignore_something` or `something.stignore`.
+/// The folder config, the policy and backups of corrupt files aren't ignore files
+fn is_ignore_file_name(name: &str) -> bool {
+    name != ".stignore"
+        && name != config::FOLDER_CONFIG
+        && name != policy::FILE
+        && !is_corrupt_backup(name)
+        && (name.starts_with(".stignore") || name.ends_with(".stignore"))
+}
+
+/// Is `name` in the `<name>.corrupt[.N]` form of `corrupt::backup_path`
+fn is_corrupt_backup(name: &str) -> bool {
+    name.rsplit_once(corrupt::SUFFIX).is_some_and(|(_, n)| {
+        n.is_empty()
+            || n.strip_prefix('.')
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+    })
+}
+
+/// Files of the folder `root` that look like ignore files, but aren't `used`.
+/// Custom folder `markers` are skipped like `.stfolder`
+pub fn orphans(root: &Path, used: &[PathBuf], markers: &[String]) -> Vec<PathBuf> {
+    let used: Vec<_> = used.iter().filter_map(|p| p.canonicalize().ok()).collect();
+    let mut orphans = Vec::new();
+    let mut dirs = vec![(root.to_path_buf(), String::new())];
+    while let Some((dir, rel)) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if rel.is_empty() {
+                name.clone()
+            } else {
+                format!("{rel}/{name}")
+            };
+            if matcher::is_internal(&path, markers) {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push((entry.path(), path)),
+                Ok(t) if t.is_file() && is_ignore_file_name(&name) => {
+                    let canonical = entry.path().canonicalize().unwrap_or(entry.path());
+                    if !used.contains(&canonical) {
+                        orphans.push(entry.path());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    orphans.sort();
+    orphans
+}
+
+/// Was the file created by stignore, so it can be safely deleted
+pub fn is_generated(backend: &dyn Backend, path: &Path) -> bool {
+    corrupt::read(backend, path).is_ok_and(|c| c.starts_with(global::GENERATED))
+}
+
+/// Contents of `file` without the `dangling` lines
+pub fn without_dangling(
+    backend: &dyn Backend,
+    file: &Path,
+    dangling: &[Dangling],
+) -> Result<String> {
+    let contents = corrupt::read(backend, file)?;
+    Ok(contents
+        .split_inclusive('\n')
+        .enumerate()
+        .filter(|(n, _)| !dangling.iter().any(|d| d.file == file && d.line == n + 1))
+        .map(|(_, line)| line)
+        .collect())
+}
@@ -0,0 +1,152 @@
+//! Splitting a single `.stignore` into local rules and shared `.stignore_sync`.
+
+use crate::{syntax, LINE_ENDING};
+
+/// Names that are junk on every machine, so rules for them can be shared
+const SHAREABLE: [&str; 24] = [
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "desktop.ini",
+    "$RECYCLE.BIN",
+    ".Trash-*",
+    ".git",
+    ".hg",
+    ".svn",
+    "node_modules",
+    ".pnpm-store",
+    "target",
+    "build",
+    "dist",
+    "__pycache__",
+    "*.pyc",
+    ".venv",
+    ".cache",
+    "*.tmp",
+    "*.swp",
+    "*~",
+    "~$*",
+    ".idea",
+    ".vscode",
+];
+
+/// Where a line of `.stignore` goes
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Layer {
+    /// Stays in `.stignore`, applies to this machine only
+    Local,
+    /// Moves to `.stignore_sync`, applies to every device
+    Shared,
+}
+
+/// Layer of pattern `line` guessed from its contents, `None` if it's up to the user.
+///
+/// `#include`s and patterns mentioning this machine's `names` (host, user)
+/// are local, patterns for well-known junk files are shared
+pub fn classify(line: &str, names: &[String]) -> Option<Layer> {
+    if line.starts_with('#') {
+        return Some(Layer::Local);
+    }
+    let lowercase = line.to_lowercase();
+    if names
+        .iter()
+        .any(|n| !n.is_empty() && lowercase.contains(&n.to_lowercase()))
+    {
+        return Some(Layer::Local);
+    }
+    let (_, pattern) = syntax::split_modifiers(line);
+    let name = pattern
+        .trim_end_matches("/**")
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(pattern);
+    SHAREABLE.contains(&name).then_some(Layer::Shared)
+}
+
+/// Contents of `.stignore` and `.stignore_sync` after moving `shared` lines of
+/// `contents` to `.stignore_sync`. Comments move together with the pattern
+/// that follows them. With `include` `#include .stignore_sync` takes the place
+/// of the first shared pattern, so the order of rules changes as little as possible
+pub fn split(contents: &str, layers: &[Layer], include: bool) -> (String, String) {
+    let mut local = Vec::new();
+    let mut shared = Vec::new();
+    let mut comments = Vec::new();
+    let mut patterns = layers.iter();
+    let mut included = !include;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with("//") {
+            comments.push(line);
+            continue;
+        }
+        if line.is_empty() {
+            local.append(&mut comments);
+            if local.last().is_some_and(|l: &&str| !l.is_empty()) {
+                local.push(line);
+            }
+            continue;
+        }
+        let layer = patterns.next().copied().unwrap_or(Layer::Local);
+        if layer == Layer::Shared {
+            if !included {
+                local.push("#include .stignore_sync");
+                included = true;
+            }
+            shared.append(&mut comments);
+            shared.push(line);
+        } else {
+            local.append(&mut comments);
+            local.push(line);
+        }
+    }
+    local.append(&mut comments);
+    while local.last().is_some_and(|l| l.is_empty()) {
+        local.pop();
+    }
+    let join = |lines: Vec<&str>| lines.iter().map(|l| format!("{l}{LINE_ENDING}")).collect();
+    (join(local), join(shared))
+}
+
+/// Lines that `split` classifies: everything except comments and blank lines
+pub fn patterns(contents: &str) -> Vec<&str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("//"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junk_is_shared_and_machine_names_are_local() {
+        let names = ["laptop".to_string()];
+        assert_eq!(
+            classify("(?d)/sub/node_modules/", &names),
+            Some(Layer::Shared)
+        );
+        assert_eq!(classify("*.pyc", &names), Some(Layer::Shared));
+        assert_eq!(classify("/Laptop-cache", &names), Some(Layer::Local));
+        assert_eq!(classify("#include other", &names), Some(Layer::Local));
+        assert_eq!(classify("/photos", &names), None);
+    }
+
+    #[test]
+    fn include_takes_the_place_of_the_first_shared_pattern() {
+        let contents = "/a\n\n// junk\n.DS_Store\n/b\n// more junk\nThumbs.db\n";
+        let layers = [Layer::Local, Layer::Shared, Layer::Local, Layer::Shared];
+        let (local, shared) = split(contents, &layers, true);
+        let lines = |s: &str| s.replace('\n', LINE_ENDING);
+        assert_eq!(local, lines("/a\n\n#include .stignore_sync\n/b\n"));
+        assert_eq!(
+            shared,
+            lines("// junk\n.DS_Store\n// more junk\nThumbs.db\n")
+        );
+        assert_eq!(patterns(contents), ["/a", ".DS_Store", "/b", "Thumbs.db"]);
+
+        let (local, _) = split(contents, &layers, false);
+        assert_eq!(local, lines("/a\n\n/b\n"));
+    }
+}
@@ -0,0 +1,316 @@
+//! Checks of whole ignore files for mistakes and inconsistencies.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    backend::Backend,
+    color::Palette,
+    corrupt,
+    matcher::{self, Rule},
+    syntax,
+};
+
+/// Problem with a line of an ignore file
+pub struct Problem {
+    pub file: PathBuf,
+    /// 1-based line number
+    pub line: usize,
+    pub text: String,
+    pub message: String,
+    /// Replacement for the line that fixes the problem
+    pub fix: Option<String>,
+}
+
+impl Problem {
+    /// Same as `Display`, with location and message highlighted
+    pub fn colored(&self, palette: &Palette) -> String {
+        format!(
+            "{} {}: {}",
+            palette.link(
+                &palette.location(&format!("{}:{}:", self.file.display(), self.line)),
+                &self.file
+            ),
+            palette.note(&self.message),
+            self.text
+        )
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.file.display(),
+            self.line,
+            self.message,
+            self.text
+        )
+    }
+}
+
+fn read_lines(backend: &dyn Backend, path: &Path) -> Result<Vec<String>> {
+    Ok(corrupt::read(backend, path)?
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// Syntax problems of the ignore file at `path`
+pub fn check_syntax(backend: &dyn Backend, path: &Path) -> Result<Vec<Problem>> {
+    let dir = path.parent().unwrap_or(path);
+    Ok(read_lines(backend, path)?
+        .into_iter()
+        .enumerate()
+        .filter_map(|(n, text)| {
+            syntax::check_line(backend, &text, dir).map(|message| Problem {
+                file: path.to_path_buf(),
+                line: n + 1,
+                text,
+                message,
+                fix: None,
+            })
+        })
+        .collect())
+}
+
+/// Rule of `earlier` ones that matches every path `rule` could match, so
+/// `rule` never decides anything. Only proven for patterns that match
+/// everything and for rooted patterns without wildcards
+pub fn shadowed_by<'a>(earlier: &'a [Rule], rule: &Rule) -> Option<&'a Rule> {
+    let (_, pattern) = syntax::split_modifiers(&rule.line);
+    let literal = pattern
+        .strip_prefix('/')
+        .map(|p| p.trim_end_matches('/'))
+        .filter(|p| !p.is_empty() && syntax::is_literal(p) && !rule.case_insensitive);
+    earlier.iter().find(|e| {
+        let (_, earlier_pattern) = syntax::split_modifiers(&e.line);
+        // matching both the path and its contents
+        syntax::matches_everything(earlier_pattern)
+            || literal.is_some_and(|p| e.matches(p) && e.matches(&format!("{p}/x")))
+    })
+}
+
+/// Rules that never take effect because an earlier rule always matches first.
+/// `rules` are all rules of the folder, in the order syncthing checks them
+pub fn check_shadowing(rules: &[Rule]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (n, rule) in rules.iter().enumerate() {
+        let (Some(by), Some((file, line))) = (shadowed_by(&rules[..n], rule), &rule.location)
+        else {
+            continue;
+        };
+        let at = match &by.location {
+            Some((file, line)) => format!(" at {}:{line}", file.display()),
+            None => String::new(),
+        };
+        problems.push(Problem {
+            file: file.clone(),
+            line: *line,
+            text: rule.line.clone(),
+            message: format!("never takes effect, {}{at} matches first", by.line),
+            fix: None,
+        });
+    }
+    problems
+}
+
+/// How ignored directories should be written
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+pub enum DirIdiom {
+    /// `dir` - ignores the directory together with its contents
+    Bare,
+    /// `dir/**` - ignores the contents, empty directory is still synced
+    Contents,
+}
+
+/// Rules for directories of the folder `root` that are not written in `idiom`.
+/// `markers` are the folder markers, skipped with other internal directories
+pub fn check_dir_rules(
+    backend: &dyn Backend,
+    root: &Path,
+    path: &Path,
+    idiom: DirIdiom,
+    markers: &[String],
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    for (n, text) in read_lines(backend, path)?.into_iter().enumerate() {
+        let line = text.trim();
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+        let (modifiers, pattern) = syntax::split_modifiers(line);
+        let contents_of = pattern
+            .strip_suffix("/**")
+            .or_else(|| pattern.strip_suffix('/'))
+            .filter(|dir| !dir.is_empty() && !dir.ends_with('*'));
+
+        let fix = match (idiom, contents_of) {
+            (DirIdiom::Bare, Some(dir)) => format!("{modifiers}{dir}"),
+            (DirIdiom::Contents, None)
+                if syntax::is_literal(pattern) && is_dir(root, pattern, markers) =>
+            {
+                format!("{modifiers}{pattern}/**")
+            }
+            _ => continue,
+        };
+        problems.push(Problem {
+            file: path.to_path_buf(),
+            line: n + 1,
+            message: match idiom {
+                DirIdiom::Bare => "directory contents are ignored instead of the directory",
+                DirIdiom::Contents => "directory is ignored instead of its contents",
+            }
+            .to_string(),
+            text,
+            fix: Some(fix),
+        });
+    }
+    Ok(problems)
+}
+
+/// Does literal `pattern` refer to a directory in the folder `root`
+fn is_dir(root: &Path, pattern: &str, markers: &[String]) -> bool {
+    match pattern.strip_prefix('/') {
+        Some(rooted) => root.join(rooted).is_dir(),
+        // unrooted patterns match at any depth
+        None => dir_exists_below(root, pattern, markers),
+    }
+}
+
+fn dir_exists_below(dir: &Path, rel: &str, markers: &[String]) -> bool {
+    if dir.join(rel).is_dir() {
+        return true;
+    }
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().any(|entry| {
+        // nested folders have their own markers
+        !matcher::is_internal(&entry.file_name().to_string_lossy(), markers)
+            && entry.file_type().is_ok_and(|t| t.is_dir())
+            && dir_exists_below(&entry.path(), rel, markers)
+    })
+}
+
+/// Estimated cost of matching paths against `pattern` (without modifiers)
+/// in syncthing, with a suggestion how to make it cheaper if it's known to be slow.
+///
+/// Every wildcard adds to the cost, `**` and unrooted patterns (tried at every
+/// depth) more so, brace alternatives each count as a separate pattern.
+pub fn complexity(pattern: &str) -> (usize, Option<String>) {
+    let rooted = pattern.starts_with('/');
+    let body = pattern.trim_start_matches('/');
+    let globstars = body.matches("**").count();
+    let stars = body.matches('*').count() - 2 * globstars;
+    let classes = body.matches('[').count() + body.matches('?').count();
+    let alternatives = body.matches(',').count() + body.matches('{').count();
+    let cost = 1 + usize::from(!rooted) + stars + classes + 3 * globstars + alternatives;
+
+    let name = body.rsplit('/').next().unwrap_or(body);
+    let hint = if syntax::matches_everything(body) {
+        Some("matches everything in the folder, every path has to be checked against the rest of the file first".to_string())
+    } else if let Some(rest) = body
+        .strip_prefix("**/")
+        .filter(|r| !r.contains('/') && name.contains(['*', '?']))
+    {
+        Some(format!(
+            "leading ** with a wildcard name, `{rest}` matches at any depth too"
+        ))
+    } else if alternatives > 8 {
+        Some(format!(
+            "{} alternatives in braces, split them into separate patterns",
+            body.matches(',').count() + 1
+        ))
+    } else if globstars > 1 {
+        Some(
+            "several ** in one pattern, each of them can match any number of directories"
+                .to_string(),
+        )
+    } else {
+        None
+    };
+    (cost, hint)
+}
+
+/// Slow patterns of the ignore file at `path`, if its total complexity is above `max`
+pub fn check_complexity(backend: &dyn Backend, path: &Path, max: usize) -> Result<Vec<Problem>> {
+    let mut total = 0;
+    let mut rules = Vec::new();
+    for (n, text) in read_lines(backend, path)?.into_iter().enumerate() {
+        let line = text.trim();
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+        let (modifiers, pattern) = syntax::split_modifiers(line);
+        let (cost, hint) = complexity(pattern);
+        total += cost;
+        let fix = pattern
+            .strip_prefix("**/")
+            .filter(|rest| {
+                !rest.contains('/') && hint.is_some() && !syntax::matches_everything(rest)
+            })
+            .map(|rest| format!("{modifiers}{rest}"));
+        rules.push((n + 1, text, cost, hint, fix));
+    }
+    if total <= max {
+        return Ok(Vec::new());
+    }
+
+    let mut problems: Vec<_> = rules
+        .iter()
+        .filter(|(.., hint, _)| hint.is_some())
+        .map(|(line, text, cost, hint, fix)| Problem {
+            file: path.to_path_buf(),
+            line: *line,
+            text: text.clone(),
+            message: format!(
+                "slow pattern (complexity {cost} of {total}, max {max}): {}",
+                hint.as_ref().unwrap()
+            ),
+            fix: fix.clone(),
+        })
+        .collect();
+    if problems.is_empty() {
+        // nothing stands out, point at the most expensive pattern
+        if let Some((line, text, cost, ..)) =
+            rules.iter().rev().max_by_key(|(_, _, cost, ..)| *cost)
+        {
+            problems.push(Problem {
+                file: path.to_path_buf(),
+                line: *line,
+                text: text.clone(),
+                message: format!(
+                    "complexity of the file is {total}, max {max}; the most expensive pattern ({cost})"
+                ),
+                fix: None,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+/// Contents of `path` with fixes for `problems` in it applied
+pub fn fixed_contents(backend: &dyn Backend, path: &Path, problems: &[Problem]) -> Result<String> {
+    let contents = corrupt::read(backend, path)?;
+    let mut out = String::with_capacity(contents.len());
+    for (n, line) in contents.split_inclusive('\n').enumerate() {
+        let fix = problems
+            .iter()
+            .find(|p| p.file == path && p.line == n + 1)
+            .and_then(|p| p.fix.as_ref());
+        match fix {
+            Some(fix) => {
+                let ending = &line[line.trim_end_matches(['\r', '\n']).len()..];
+                out.push_str(fix);
+                out.push_str(ending);
+            }
+            None => out.push_str(line),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,41 @@
+//! Logger for `-v` messages, printed to stderr.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        eprintln!("[{level}] {}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Enables messages according to the number of `-v` flags:
+/// one shows decisions stignore makes, two also show every step and file access
+pub fn init(verbosity: u8) {
+    let level = match verbosity {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    // only fails if called twice
+    let _ = log::set_logger(&Logger);
+    log::set_max_level(level);
+}
@@ -1,32 +1,158 @@
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::{prelude::*, BufRead, BufReader, SeekFrom, Write},
+    io::{prelude::*, IsTerminal, Write},
     path::{self, Path, PathBuf},
+    process::{Command as Process, ExitCode},
 };
 
 use anyhow::{bail, Context, Result};
-use clap::{Parser, ValueEnum};
+use backend::Backend;
+use clap::{
+    parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
+use clap_complete::Shell;
+use log::{debug, info};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+mod backend;
+mod bench;
+mod color;
+mod config;
+mod corrupt;
+mod emit;
+mod fragments;
+mod global;
+mod includes;
+mod layered;
+mod lint;
+mod logger;
+mod matcher;
+mod owners;
+mod policy;
+mod sanitize;
+mod section;
+mod stats;
+mod syncthing;
+mod syntax;
+mod tidy;
+mod winpath;
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
 #[clap(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 enum Target {
     Auto,
     Stignore,
     StignoreSync,
+    Both,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+enum OnMissingFolder {
+    Fail,
+    CreateMarker,
+    Choose,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+enum TargetMissing {
+    Create,
+    Skip,
+}
+
+/// Unicode normalization form of added patterns
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum UnicodeForm {
+    /// NFD on macOS, none elsewhere
+    Auto,
+    /// Keep patterns as they were typed
+    None,
+    /// Composed, what keyboards usually produce
+    Nfc,
+    /// Decomposed, how macOS usually stores names
+    Nfd,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum BackendKind {
+    Local,
+    Memory,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Backup {
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum NonInteractive {
+    Fail,
+    DryRun,
 }
 
 /// Adds syncthing ignore patterns (https://docs.syncthing.net/users/ignoring)
 /// to parent syncthing folder of the current working directory.
 ///
 /// Source code & examples: https://github.com/Andrew-Morozko/stignore
-#[derive(Parser, Debug)]
-#[clap(version, about, global_setting(clap::AppSettings::DeriveDisplayOrder))]
+#[derive(Parser, Clone, Debug)]
+#[clap(
+    version,
+    about,
+    global_setting(clap::AppSettings::DeriveDisplayOrder),
+    args_conflicts_with_subcommands(true),
+    subcommand_negates_reqs(true)
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Patterns to add
-    #[clap(value_parser, required(true), min_values(1))]
+    ///
+    /// @FILE reads patterns from FILE, one per line, `-` reads them from stdin.
+    /// Patterns named like subcommands go after `--`: `stignore -- list`
+    #[clap(
+        value_parser,
+        required_unless_present_any(["from-file", "from-clipboard"]),
+        min_values(1)
+    )]
     pattern: Vec<String>,
 
+    /// Read patterns from FILE, one per line
+    ///
+    /// Each line is processed the same way as patterns passed as arguments.
+    /// `-` reads stdin. Can be repeated
+    #[clap(long, value_parser, value_name = "FILE", multiple_occurrences(true))]
+    from_file: Vec<PathBuf>,
+
+    /// Read patterns from the system clipboard
+    ///
+    /// Uses pbpaste on macOS, wl-paste, xclip or xsel on Linux and
+    /// PowerShell on Windows
+    #[clap(long)]
+    from_clipboard: bool,
+
+    /// Add patterns to every folder listed in syncthing's config.xml
+    ///
+    /// Patterns are added as-is, like with --absolute
+    #[clap(long, value_parser, conflicts_with_all(&["root", "relative-to", "paths", "on-missing-folder"]))]
+    all_folders: bool,
+
+    /// Patterns read by `pattern_inputs`, so stdin and the clipboard are read once
+    #[clap(skip)]
+    inputs: Option<Vec<String>>,
+
+    /// Folder markers found by `markers`, so config.xml is read once
+    #[clap(skip)]
+    markers: std::cell::OnceCell<Vec<String>>,
+
     /// Specify which file would be appended with patterns
     ///
     /// auto - append patterns to .stignore_sync if it is included in .stignore,
@@ -35,263 +161,5830 @@ struct Args {
     /// stignore - append patterns to .stignore, create if doesn't exist
     ///
     /// stignore_sync - append patterns to .stignore_sync, create if doesn't exist
-    #[clap(short, long, arg_enum, value_parser, default_value_t = Target::Auto)]
+    ///
+    /// both - append patterns to .stignore and .stignore_sync, only when adding patterns
+    #[clap(
+        short,
+        long,
+        arg_enum,
+        value_parser,
+        default_value_t = Target::Auto,
+        env = "STIGNORE_TARGET",
+        global(true)
+    )]
     target: Target,
 
+    /// Work with ignore file PATH, relative to the folder root, instead of
+    /// the one selected by --target. It has to be included from .stignore,
+    /// directly or through other includes
+    #[clap(
+        long,
+        value_parser,
+        value_name = "PATH",
+        env = "STIGNORE_FILE",
+        global(true)
+    )]
+    file: Option<PathBuf>,
+
+    /// Work with fragment .stignore.d/NAME.stignore, like --file. Adding
+    /// patterns to a fragment that isn't included yet includes it from .stignore
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        conflicts_with("file"),
+        global(true)
+    )]
+    fragment: Option<String>,
+
+    /// Run as if stignore was started in DIR: the folder, patterns and paths
+    /// given as arguments are looked up from there
+    #[clap(long, value_parser, value_name = "DIR", global(true))]
+    cwd: Option<PathBuf>,
+
+    /// Name of the folder marker, if it was changed from .stfolder in syncthing's
+    /// folder settings. By default marker names from syncthing's config.xml are used
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        env = "STIGNORE_MARKER",
+        global(true)
+    )]
+    marker: Option<String>,
+
+    /// Use the logical CWD (as the shell shows it, from $PWD) instead of resolving
+    /// symlinks, so a symlinked directory inside of the folder is prefixed by
+    /// its own path
+    #[clap(long, value_parser, global(true))]
+    no_resolve_symlinks: bool,
+
+    /// Use DIR as the syncthing folder root, even if it has no .stfolder marker
+    ///
+    /// Outside of DIR patterns are relative to its root
+    #[clap(
+        long,
+        value_parser,
+        value_name = "DIR",
+        env = "STIGNORE_ROOT",
+        global(true)
+    )]
+    root: Option<PathBuf>,
+
+    /// When syncthing folders are nested, use the one closest to CWD without asking
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(&["outermost", "root"]),
+        global(true)
+    )]
+    innermost: bool,
+
+    /// When syncthing folders are nested, use the one furthest from CWD without asking
+    #[clap(long, value_parser, conflicts_with("root"), global(true))]
+    outermost: bool,
+
+    /// Prepend path to DIR relative to syncthing folder root instead of path
+    /// to CWD. The folder is looked for starting from DIR too
+    #[clap(long, value_parser, value_name = "DIR", global(true))]
+    relative_to: Option<PathBuf>,
+
+    /// Routes from config files, --file and --fragment disable them
+    #[clap(skip)]
+    routes: Vec<config::Route>,
+
+    /// Syncthing instances of other devices from config files
+    #[clap(skip)]
+    remotes: BTreeMap<String, config::Remote>,
+
+    /// GUI of the local syncthing from config files, overrides config.xml
+    #[clap(skip)]
+    api: Option<config::Remote>,
+
     /// Copy patterns as-is
     ///
     /// Don't prepend path to CWD relative to syncthing folder root
-    #[clap(short, long, value_parser)]
+    #[clap(
+        short,
+        long,
+        action = clap::ArgAction::SetTrue,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        env = "STIGNORE_ABSOLUTE"
+    )]
     absolute: bool,
 
+    /// Treat patterns as paths of existing files and directories
+    ///
+    /// Paths are relative to the current directory (or absolute) and must be
+    /// inside of the syncthing folder. Patterns matching exactly these paths
+    /// are generated, glob characters in names are escaped.
+    #[clap(long, value_parser, conflicts_with("absolute"))]
+    paths: bool,
+
+    /// Treat patterns as literal names, escaping glob characters
+    ///
+    /// `foo[1].txt` only matches a file with exactly this name. Modifiers like
+    /// (?d) aren't recognized. On Windows glob characters can't be escaped
+    #[clap(long, value_parser, conflicts_with("paths"))]
+    literal: bool,
+
+    /// Add (?i) to patterns, so they match regardless of case
+    #[clap(long, value_parser)]
+    case_insensitive: bool,
+
+    /// Add (?i) to patterns if the folder is on a case-insensitive file system
+    ///
+    /// Makes patterns behave the same way the local file system does
+    /// (default volumes on Windows and macOS), and the same on peers with other OSes
+    #[clap(
+        long,
+        action = clap::ArgAction::SetTrue,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        env = "STIGNORE_AUTO_CASE_INSENSITIVE"
+    )]
+    auto_case_insensitive: bool,
+
+    /// Unicode normalization form of added patterns and prefixes
+    ///
+    /// Names with accents can be stored composed (NFC) or decomposed (NFD) and
+    /// patterns only match the same form. By default patterns are decomposed on macOS
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        value_name = "FORM",
+        default_value_t = UnicodeForm::Auto,
+        env = "STIGNORE_UNICODE_FORM"
+    )]
+    unicode_form: UnicodeForm,
+
+    /// Add (?d) to patterns, so matching files don't prevent deletion of
+    /// directories that contain them
+    #[clap(long, value_parser)]
+    deletable: bool,
+
+    /// Add ! to patterns, so matching files are synced.
+    /// Keep in mind that the first matching pattern wins
+    #[clap(long, value_parser)]
+    negate: bool,
+
+    /// Treat patterns as directories and write them in one form, whatever
+    /// way they were typed (`build`, `build/` or `build/**`). Patterns naming
+    /// existing files are kept as-is
+    ///
+    /// bare - `dir`, ignores the directory together with its contents
+    ///
+    /// contents - `dir/**`, ignores the contents, empty directory is still synced
+    #[clap(long, arg_enum, value_parser, value_name = "IDIOM")]
+    dir: Option<lint::DirIdiom>,
+
+    /// Ask syncthing to rescan the folder and wait until the scan is done
+    ///
+    /// Uses syncthing's REST API, then reports how the amount of local and
+    /// global data changed.
+    #[clap(long, value_parser, conflicts_with_all(&["fast-append", "emit-commands"]))]
+    retry_rescan: bool,
+
+    /// Flush changed ignore files to disk before exiting
+    ///
+    /// Patterns can't be lost if the machine crashes or runs out of battery
+    /// right after stignore finishes, at the cost of slower writes
+    #[clap(long, value_parser, env = "STIGNORE_FSYNC", global(true))]
+    fsync: bool,
+
+    /// Where changes of ignore files go
+    ///
+    /// local - the files on disk
+    ///
+    /// memory - nowhere: files are read from disk, changes are only printed
+    /// when stignore finishes, try out commands with it
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        default_value_t = BackendKind::Local,
+        env = "STIGNORE_BACKEND",
+        global(true)
+    )]
+    backend: BackendKind,
+
+    /// What to do with a corrupt ignore file that patterns are added to
+    ///
+    /// ask - offer to move it to a .corrupt backup and start a fresh one, in a
+    /// terminal or with --yes
+    ///
+    /// always - move it to the backup without asking
+    ///
+    /// never - refuse to change it
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        default_value_t = Backup::Ask,
+        env = "STIGNORE_BACKUP"
+    )]
+    backup: Backup,
+
+    /// Seconds to wait for the rescan requested by --retry-rescan
+    #[clap(long, value_parser, value_name = "SECONDS", default_value_t = 60)]
+    rescan_timeout: u64,
+
     /// Display planned changes and wait for confirmation
-    #[clap(short, long, value_parser, conflicts_with("silent"))]
+    #[clap(
+        short,
+        long,
+        action = clap::ArgAction::SetTrue,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        conflicts_with("silent"),
+        env = "STIGNORE_PREVIEW"
+    )]
     preview: bool,
 
+    /// Answer "yes" to all questions
+    ///
+    /// Combined with --preview still displays planned changes, but doesn't wait
+    /// for confirmation
+    #[clap(short, long, value_parser, global(true))]
+    yes: bool,
+
+    /// What to do when confirmation is required, but stignore isn't running in a terminal
+    ///
+    /// fail - exit with an error
+    ///
+    /// dry-run - only display planned changes
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        default_value_t = NonInteractive::Fail,
+        env = "STIGNORE_NON_INTERACTIVE"
+    )]
+    non_interactive: NonInteractive,
+
+    /// What to do when the current directory isn't inside of a syncthing folder
+    ///
+    /// fail - exit with an error
+    ///
+    /// create-marker - make the current directory a syncthing folder root by
+    /// creating .stfolder in it (asks for confirmation)
+    ///
+    /// choose - pick one of the folders from syncthing's config (terminal only),
+    /// patterns are relative to its root
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        value_name = "ACTION",
+        default_value_t = OnMissingFolder::Fail,
+        env = "STIGNORE_ON_MISSING_FOLDER",
+        global(true)
+    )]
+    on_missing_folder: OnMissingFolder,
+
+    /// What to do when --target is stignore_sync, but .stignore_sync doesn't
+    /// exist or isn't included in .stignore yet
+    ///
+    /// create - create .stignore_sync and add `#include .stignore_sync` to .stignore
+    ///
+    /// skip - don't change anything and exit successfully with a warning
+    ///
+    /// Without this option patterns are appended to .stignore_sync anyway,
+    /// with a warning if it isn't included
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        value_name = "ACTION",
+        env = "STIGNORE_IGNORE_TARGET_MISSING",
+        conflicts_with_all(&["fast-append", "emit-commands"])
+    )]
+    ignore_target_missing: Option<TargetMissing>,
+
+    /// Add `#include .stignore_sync` to .stignore if patterns are appended to
+    /// .stignore_sync and it isn't included yet. Without this option you are
+    /// asked about it in a terminal
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(&["fast-append", "emit-commands", "ignore-target-missing"])
+    )]
+    ensure_include: bool,
+
     /// Don't display messages
-    #[clap(short, long, value_parser)]
+    #[clap(
+        short,
+        long,
+        action = clap::ArgAction::SetTrue,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        conflicts_with("verbose"),
+        env = "STIGNORE_SILENT"
+    )]
     silent: bool,
+
+    /// Explain what stignore does, repeat (-vv) for more details
+    ///
+    /// -v shows which folder and target file were picked and why,
+    /// -vv also shows include resolution and every file access
+    #[clap(short, long, action = clap::ArgAction::Count, global(true))]
+    verbose: u8,
+
+    /// Append patterns with a single write, without reading any files
+    ///
+    /// Skips the .stignore_sync include check (auto target resolves to .stignore)
+    /// and doesn't make sure that the file ends with a new line. Config files
+    /// aren't read, custom markers from config.xml aren't looked for (pass
+    /// --marker if the folder uses one) and the innermost of nested folders is
+    /// used without asking. Intended for scripts that invoke stignore many
+    /// times in a row.
+    #[clap(long, value_parser, conflicts_with_all(&["preview", "dir", "paths"]))]
+    fast_append: bool,
+
+    /// Print a JSON report instead of messages
+    ///
+    /// The report contains syncthing folder root, target file, appended patterns,
+    /// skipped lines, warnings and errors.
+    #[clap(long, value_parser, conflicts_with("preview"))]
+    json: bool,
+
+    /// Print a shell script that makes the planned changes instead of making them
+    ///
+    /// The script skips patterns that are already present, so it can be run
+    /// repeatedly, e.g. by configuration management on other machines.
+    #[clap(long, value_parser, conflicts_with_all(&["preview", "fast-append", "json"]))]
+    emit_commands: bool,
+
+    /// Print patterns that would be added instead of adding them, e.g. when
+    /// ignore files aren't writable: `stignore --stdout PATTERN | sudo tee -a .stignore`
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(&["preview", "fast-append", "emit-commands", "json"])
+    )]
+    stdout: bool,
+
+    /// Insert patterns into their alphabetical positions within the last group
+    /// of patterns of the target file instead of appending them
+    ///
+    /// Groups are separated by comments and blank lines. Patterns are appended
+    /// as usual if the group has negations, since their order matters.
+    #[clap(long, value_parser, conflicts_with_all(&["fast-append", "emit-commands"]))]
+    sorted: bool,
+
+    /// Write a `// Added <date> by <user>@<host>` comment above appended
+    /// patterns, so it's clear where the rules of shared files come from
+    #[clap(
+        long,
+        action = clap::ArgAction::SetTrue,
+        value_parser = clap::builder::BoolishValueParser::new(),
+        env = "STIGNORE_PROVENANCE"
+    )]
+    provenance: bool,
+
+    /// Insert patterns at the top of the target file instead of appending them,
+    /// so they take precedence over the patterns already there
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(&["fast-append", "emit-commands", "sorted", "section"])
+    )]
+    prepend: bool,
+
+    /// Insert patterns before the first #include of the target file, so they
+    /// take precedence over the included patterns. Appends if there are no #includes
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all(&["fast-append", "emit-commands", "sorted", "section", "prepend"])
+    )]
+    before_includes: bool,
+
+    /// Explain why the patterns are added, written as a `//` comment above them
+    #[clap(short = 'm', long, value_parser, value_name = "REASON")]
+    comment: Option<String>,
+
+    /// Add patterns to the end of section NAME, started by a `// --- NAME ---`
+    /// comment, instead of the end of the file. The section is created if
+    /// it's missing
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        conflicts_with_all(&["fast-append", "emit-commands"])
+    )]
+    section: Option<String>,
+
+    /// Append patterns even if the target file already contains them
+    #[clap(long, value_parser)]
+    allow_duplicates: bool,
+
+    /// Skip patterns present in .stignore or any file included from it,
+    /// not only the ones in the target file
+    #[clap(long, value_parser, conflicts_with("allow-duplicates"))]
+    check_includes: bool,
+
+    /// Append patterns even if every path they match is already ignored by
+    /// an existing pattern
+    #[clap(long, value_parser)]
+    allow_redundant: bool,
+
+    /// Add patterns even if they ignore everything in the folder, syncthing's
+    /// internal files or the ignore files themselves
+    #[clap(long, value_parser)]
+    force: bool,
+
+    /// Don't warn about patterns naming paths that don't exist
+    #[clap(long, value_parser)]
+    no_check: bool,
+
+    /// List files of the folder that each new pattern would ignore
+    #[clap(long, value_parser)]
+    show_matches: bool,
+
+    /// Don't pipe long output through $PAGER
+    #[clap(long, value_parser, global(true))]
+    no_pager: bool,
+
+    /// When to use colors
+    ///
+    /// auto - if the output is a terminal and $NO_COLOR isn't set
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        value_name = "WHEN",
+        default_value_t = color::ColorChoice::Auto,
+        env = "STIGNORE_COLOR",
+        global(true)
+    )]
+    color: color::ColorChoice,
+
+    /// Use defaults from [profile.NAME] section of config files
+    #[clap(
+        long,
+        value_parser,
+        value_name = "NAME",
+        env = "STIGNORE_PROFILE",
+        global(true)
+    )]
+    profile: Option<String>,
 }
 
-#[cfg(windows)]
-const LINE_ENDING: &str = "\r\n";
-#[cfg(not(windows))]
-const LINE_ENDING: &str = "\n";
+#[derive(Subcommand, Clone, Debug)]
+enum Command {
+    /// Generate shell completion script
+    Completions(CompletionsArgs),
+    /// Print contents of the ignore file selected by --target
+    Export(ExportArgs),
+    /// Open the ignore file selected by --target in $VISUAL or $EDITOR
+    Edit(EditArgs),
+    /// Display how much data of the folder is ignored
+    Stats(StatsArgs),
+    /// Display how much ignored data each top-level directory of the folder has
+    Du,
+    /// Delete ignored files of the folder, only lists them by default
+    Clean(CleanArgs),
+    /// Offer to ignore build artifacts (node_modules, target, ...) in the current directory
+    Suggest(SuggestArgs),
+    /// Check .stignore and files included from it for problems
+    Lint(LintArgs),
+    /// List patterns that match nothing in the folder
+    Unused(UnusedArgs),
+    /// Measure how fast paths are matched against ignore patterns
+    Bench(BenchArgs),
+    /// Manage patterns that are included into every folder
+    Global(GlobalArgs),
+    /// List .stignore and files included from it
+    List(ListArgs),
+    /// Show or change ignoreDelete option of the folder via syncthing's REST API
+    IgnoreDelete(IgnoreDeleteArgs),
+    /// Remove patterns from the ignore file selected by --target
+    Remove(RemoveArgs),
+    /// Compare ignore patterns syncthing uses for the folder on this and other devices
+    Diff(DiffArgs),
+    /// Reorganize ignore files of the folder
+    Migrate(MigrateArgs),
+    /// Show whether paths are ignored and which patterns decide that
+    Test(TestArgs),
+    /// Add patterns of another syncthing folder to the current one
+    CopyFrom(CopyFromArgs),
+    /// Show when and on which machine a pattern was added, from --provenance comments
+    Blame(BlameArgs),
+    /// Include every fragment of .stignore.d into .stignore
+    Assemble,
+    /// Remove duplicates and extra blank lines from the ignore file selected
+    /// by --target, optionally sorting patterns
+    Tidy(TidyArgs),
+    /// Print completion candidates, used by completion scripts
+    #[clap(name = "completion-candidates", hide = true)]
+    Complete(CompleteArgs),
+}
 
-fn find_syncthing_dir() -> Result<(PathBuf, PathBuf)> {
-    let cwd = std::env::current_dir()
-        .and_then(std::fs::canonicalize)
-        .context("Can't determine current working directory")?;
-    let mut st_dir = cwd.clone();
-    loop {
-        st_dir.push(".stfolder");
-        let found = st_dir.is_dir();
-        st_dir.pop();
-        if found {
-            break;
-        }
-        if !st_dir.pop() {
-            bail!("Current directory is not inside of a syncthing folder");
-        }
-    }
+#[derive(clap::Args, Clone, Debug)]
+struct RemoveArgs {
+    /// Patterns to remove, the same as they were added
+    #[clap(value_parser, required(true), min_values(1))]
+    pattern: Vec<String>,
 
-    let prefix = path::Path::join(
-        path::Path::new(path::Component::RootDir.as_os_str()),
-        cwd.strip_prefix(&st_dir).unwrap(),
-    );
+    /// Remove patterns as-is
+    ///
+    /// Don't prepend path to CWD relative to syncthing folder root
+    #[clap(short, long, value_parser)]
+    absolute: bool,
+}
 
-    Ok((st_dir, prefix))
+#[derive(clap::Args, Clone, Debug)]
+struct TestArgs {
+    /// Paths to check, relative to the current directory. They don't have to exist
+    #[clap(value_parser, required(true), min_values(1))]
+    path: Vec<String>,
+
+    /// Show how the deciding pattern is compiled: modifiers, anchoring,
+    /// glob tokens and the resulting regex
+    #[clap(long, value_parser)]
+    explain_regex: bool,
 }
 
-fn process_patterns(patterns: &[String], prepend_prefix: Option<&PathBuf>) -> Result<String> {
-    let re = Regex::new(r"^((?:#include )|(?:(?:\(\?[di]\)|!))*) *(.+)$").unwrap();
+#[derive(clap::Args, Clone, Debug)]
+struct DiffArgs {
+    /// Names of `[remotes.NAME]` from config files to compare with, all by default
+    ///
+    /// Without remotes, compares patterns syncthing has loaded with .stignore on disk
+    #[clap(value_parser, value_name = "REMOTE")]
+    remotes: Vec<String>,
+}
 
-    let mut out_str = String::new();
-    let mut errs = Vec::new();
+#[derive(clap::Args, Clone, Debug)]
+struct CopyFromArgs {
+    /// Root of the folder, or its label or ID from syncthing's config.xml
+    #[clap(value_parser)]
+    folder: String,
 
-    let patterns = patterns.iter().flat_map(|t| t.split('\n'));
+    /// Only copy patterns that GLOB matches, e.g. `**/node_modules`
+    #[clap(long, value_parser, value_name = "GLOB")]
+    filter: Option<String>,
+}
 
-    for mut pattern in patterns {
-        pattern = pattern.trim();
-        if pattern.is_empty() || pattern.starts_with("//") {
-            // empty pattern results in an extra new line inserted
-            out_str.push_str(pattern);
-            out_str.push_str(LINE_ENDING);
-            continue;
-        }
-        let m = re.captures(pattern);
-        if m.is_none() {
-            errs.push(pattern);
-            continue;
-        }
-        let m = m.unwrap();
-        let pattern_path = m.get(2);
-        if pattern_path.is_none() {
-            errs.push(pattern);
-            continue;
-        }
-        let pattern_path = pattern_path.unwrap();
+#[derive(clap::Args, Clone, Debug)]
+struct BlameArgs {
+    /// Pattern to look up, the same as it was added, or a line of an ignore file
+    #[clap(value_parser)]
+    pattern: String,
 
-        if let Some(m) = m.get(1) {
-            out_str.push_str(m.as_str());
-        }
+    /// Look up the pattern as-is
+    ///
+    /// Don't prepend path to CWD relative to syncthing folder root
+    #[clap(short, long, value_parser)]
+    absolute: bool,
+}
 
-        match prepend_prefix {
-            None => {
-                out_str.push_str(pattern_path.as_str());
-            }
-            Some(prefix) => {
-                let pattern_path = Path::new(pattern_path.as_str());
-                out_str.push_str(
-                    prefix
-                        .components()
-                        .chain(pattern_path.components().skip_while(|m| {
-                            matches!(
-                                m,
-                                path::Component::RootDir
-                                    | path::Component::Prefix(_)
-                                    | path::Component::CurDir
-                            )
-                        }))
-                        .collect::<PathBuf>()
-                        .display()
-                        .to_string()
-                        .as_str(),
-                );
-            }
-        }
-        out_str.push_str(LINE_ENDING);
-    }
+#[derive(clap::Args, Clone, Debug)]
+struct CompleteArgs {
+    /// Subcommand to complete arguments of
+    #[clap(value_parser)]
+    command: String,
 
-    if !errs.is_empty() {
-        bail!(
-            "Incorrect pattern{}:\n{}",
-            if errs.len() > 1 { "s" } else { "" },
-            errs.join("\n")
-        );
-    }
-    if out_str.trim().is_empty() {
-        bail!("No patterns supplied!")
-    }
-    Ok(out_str)
+    /// Word being completed
+    #[clap(value_parser, default_value = "")]
+    current: String,
 }
 
-enum PathOrFile {
-    Path(PathBuf),
-    File(PathBuf, File),
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+enum Toggle {
+    On,
+    Off,
 }
 
-impl PathOrFile {
-    fn open(&mut self) -> Result<&mut File, std::io::Error> {
-        match self {
-            Self::File(_, ref mut f) => Ok(f),
-            Self::Path(ref mut p) => {
-                let f = File::options()
-                    .read(true)
-                    .write(true)
-                    .create(true)
-                    .open(&p)?;
-                *self = Self::File(std::mem::take(p), f);
-                if let Self::File(_, f) = self {
-                    return Ok(f);
-                }
-                unreachable!()
-            }
-        }
+#[derive(clap::Args, Clone, Debug)]
+struct IgnoreDeleteArgs {
+    /// New state of the option, current state is displayed if omitted
+    ///
+    /// With ignoreDelete on, files deleted on other devices are kept on this one
+    #[clap(arg_enum, value_parser)]
+    state: Option<Toggle>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct ListArgs {
+    /// List ignore files included by no one and #includes of missing files instead
+    #[clap(long, value_parser)]
+    unused_includes: bool,
+
+    /// Remove unused files generated by stignore and #includes of missing files
+    ///
+    /// Planned changes are displayed and have to be confirmed
+    #[clap(long, value_parser, conflicts_with_all(&["limit", "offset"]))]
+    gc: bool,
+
+    #[clap(flatten)]
+    page: PageArgs,
+}
+
+/// Part of a long listing to print
+#[derive(clap::Args, Clone, Copy, Debug)]
+struct PageArgs {
+    /// Print at most N entries
+    #[clap(long, value_parser, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Skip the first N entries
+    #[clap(long, value_parser, value_name = "N", default_value_t = 0)]
+    offset: usize,
+}
+
+impl PageArgs {
+    /// The part of `items` to print and how many of them are left after it
+    fn page<'a, T>(&self, items: &'a [T]) -> (&'a [T], usize) {
+        let start = self.offset.min(items.len());
+        let end = match self.limit {
+            Some(limit) => start.saturating_add(limit).min(items.len()),
+            None => items.len(),
+        };
+        (&items[start..end], items.len() - end)
     }
-    fn path(&self) -> &Path {
-        match self {
-            Self::File(ref p, _) => p,
-            Self::Path(ref p) => p,
+
+    /// Tells that `left` entries weren't printed because of --limit
+    fn note_left(&self, args: &Args, left: usize, shown: usize) {
+        if left > 0 && !args.quiet() {
+            eprintln!(
+                "{} ...and {left} more, pass --offset {} to see them",
+                color::Palette::stderr(args.color).note("NOTE:"),
+                self.offset + shown
+            );
         }
-        .as_path()
     }
 }
 
-fn is_stignore_sync_included(stignore: &mut PathOrFile) -> Result<bool> {
-    let re = Regex::new(r"^\s*#include\s+\.stignore_sync\s*$").unwrap();
-    let f = stignore.open()?;
+#[derive(clap::Args, Clone, Debug)]
+struct GlobalArgs {
+    #[clap(subcommand)]
+    command: GlobalCommand,
+}
 
-    Ok(BufReader::new(f)
-        .lines()
-        .find_map(|p| match p {
-            Ok(ref t) => {
-                if re.is_match(t) {
-                    Some(Ok(()))
-                } else {
-                    None
-                }
-            }
-            Err(e) => Some(Err(e)),
-        })
-        .transpose()?
-        .is_some())
+#[derive(Subcommand, Clone, Debug)]
+enum GlobalCommand {
+    /// Add patterns to the global ignore file and update all registered folders
+    Add {
+        /// Patterns to add, copied as-is
+        #[clap(value_parser, required(true), min_values(1))]
+        pattern: Vec<String>,
+    },
+    /// Include global patterns into the current folder and update all registered folders
+    Sync,
 }
 
-fn append(f: &mut PathOrFile, patterns: &String) -> Result<()> {
-    let f = f.open()?;
-    let file_len = f.seek(SeekFrom::End(0))?;
-    let prepend_new_line = if file_len == 0 {
-        false
-    } else if file_len < (LINE_ENDING.len() as u64) {
-        true
-    } else {
-        let mut buf = [0u8; LINE_ENDING.len()];
-        f.seek(SeekFrom::End(-(LINE_ENDING.len() as i64)))?;
-        f.read_exact(&mut buf)?;
-        !buf.ends_with(LINE_ENDING.as_bytes())
-    };
+#[derive(clap::Args, Clone, Debug)]
+struct BenchArgs {
+    /// Ignore file to benchmark, .stignore of the current folder by default
+    #[clap(long, value_parser, value_name = "FILE")]
+    rules: Option<PathBuf>,
 
-    if prepend_new_line {
-        f.write_all(LINE_ENDING.as_bytes())?;
-    };
+    /// Number of paths to match
+    #[clap(long, value_parser, value_name = "N", default_value_t = 10000)]
+    paths: usize,
 
-    f.write_all(patterns.as_bytes())?;
+    /// Match paths of the current syncthing folder instead of generated ones
+    #[clap(long, value_parser)]
+    real: bool,
+}
 
-    Ok(())
+#[derive(clap::Args, Clone, Debug)]
+struct LintArgs {
+    /// Check that directories are ignored in a consistent way
+    ///
+    /// bare - `dir`, ignores the directory together with its contents
+    ///
+    /// contents - `dir/**`, ignores the contents, empty directory is still synced
+    #[clap(long, arg_enum, value_parser, value_name = "IDIOM")]
+    ensure_trailing_dir_rules: Option<lint::DirIdiom>,
+
+    /// Warn about slow patterns when the estimated matching complexity of an
+    /// ignore file is above N
+    ///
+    /// Every pattern costs at least 1, wildcards, `**`, unrooted patterns and
+    /// brace alternatives add to it.
+    #[clap(long, value_parser, value_name = "N")]
+    max_complexity: Option<usize>,
+
+    /// Rewrite ignore files, fixing problems that can be fixed automatically
+    #[clap(long, value_parser)]
+    fix: bool,
 }
 
-fn go(args: &Args) -> Result<()> {
-    let (st_dir, prefix) = find_syncthing_dir()?;
+#[derive(clap::Args, Clone, Debug)]
+struct UnusedArgs {
+    /// Remove unused patterns, asking about each of them (all of them with --yes)
+    #[clap(long, value_parser, conflicts_with_all(&["limit", "offset"]))]
+    remove: bool,
 
-    let patterns = process_patterns(
-        &args.pattern,
-        if args.absolute { None } else { Some(&prefix) },
-    )?;
+    #[clap(flatten)]
+    page: PageArgs,
+}
 
-    let mut stignore = PathOrFile::Path(st_dir.join(".stignore"));
-    let stignore_sync = st_dir.join(".stignore_sync");
+#[derive(clap::Args, Clone, Debug)]
+struct CleanArgs {
+    /// Delete the files, without it they are only listed
+    #[clap(long, value_parser)]
+    delete: bool,
 
-    let resolved_target = if args.target == Target::Auto {
-        let sync_included =
-            is_stignore_sync_included(&mut stignore).context("Can't read .stignore file")?;
-        if sync_included {
-            Target::StignoreSync
-        } else {
-            if !args.silent && stignore_sync.is_file() {
-                eprintln!(
-                    "NOTE: .stignore_sync exists, but wasn't included in .stignore. \
-                    Working with .stignore"
-                );
-            }
-            Target::Stignore
-        }
-    } else {
-        args.target
-    };
+    /// Ask before deleting each file or ignored directory
+    #[clap(short, long, value_parser, requires("delete"))]
+    interactive: bool,
+}
 
-    let mut tgt_file = match resolved_target {
-        Target::Stignore => stignore,
-        Target::StignoreSync => {
-            drop(stignore);
-            PathOrFile::Path(stignore_sync)
-        }
-        Target::Auto => unreachable!("Target::Auto was resolved into concrete targets"),
-    };
+#[derive(clap::Args, Clone, Debug)]
+struct SuggestArgs {
+    /// Suggest N largest files and directories of the current directory
+    /// instead of build artifacts
+    #[clap(
+        long,
+        value_parser,
+        value_name = "N",
+        min_values(0),
+        default_missing_value("10")
+    )]
+    by_size: Option<usize>,
 
-    if !args.silent {
-        println!("Appending to {}:\n{patterns}", tgt_file.path().display());
-    }
-    if args.preview {
-        use question::{Answer, Question};
-        let res = Question::new("Proceed?")
-            .until_acceptable()
-            .default(Answer::YES)
-            .show_defaults()
-            .confirm();
-        if res == Answer::NO {
-            println!("Aborting.");
-            return Ok(());
-        }
-    }
-    append(&mut tgt_file, &patterns).context("Can't append to file")
+    /// List suggestions grouped by the user that owns them, with a command
+    /// that ignores each group, instead of asking
+    #[clap(long, value_parser)]
+    by_owner: bool,
+
+    /// List suggestions grouped by how long ago they were last accessed, with
+    /// a command that ignores each group, instead of asking
+    #[clap(long, value_parser)]
+    by_age: bool,
+
+    #[clap(flatten)]
+    page: PageArgs,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let res = go(&args);
-    if args.silent && res.is_err() {
-        std::process::exit(1);
-    }
-    res
+#[derive(clap::Args, Clone, Debug)]
+struct StatsArgs {
+    /// Print shields.io endpoint badge JSON (https://shields.io/badges/endpoint-badge)
+    #[clap(long, value_parser)]
+    badge: bool,
+
+    /// List N biggest ignored files and directories, with patterns that ignore them
+    #[clap(long, value_parser, value_name = "N", conflicts_with("badge"))]
+    largest_ignored: Option<usize>,
+
+    /// List N biggest synced files, with commands that would ignore them
+    #[clap(long, value_parser, value_name = "N", conflicts_with("badge"))]
+    largest_synced: Option<usize>,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct MigrateArgs {
+    /// Move rules that can be shared to .stignore_sync and include it from .stignore
+    ///
+    /// Rules for junk files every machine has are shared, #includes and
+    /// patterns mentioning this machine's host or user name stay local. You are
+    /// asked about the rest (outside of a terminal they stay local).
+    #[clap(long, value_parser)]
+    to_layered: bool,
+
+    /// Move PATTERNs from .stignore to .stignore_sync, including it from .stignore
+    /// if needed. Without PATTERNs picks rules that can be shared, like --to-layered
+    #[clap(long, value_parser, conflicts_with("to-layered"))]
+    to_sync: bool,
+
+    /// Move PATTERNs from .stignore_sync to .stignore, right above
+    /// `#include .stignore_sync`. Without PATTERNs moves all of them
+    #[clap(long, value_parser, conflicts_with_all(&["to-layered", "to-sync"]))]
+    to_local: bool,
+
+    /// Patterns to move, the same as they were added
+    #[clap(value_parser, conflicts_with("to-layered"))]
+    pattern: Vec<String>,
+
+    /// Move patterns as-is
+    ///
+    /// Don't prepend path to CWD relative to syncthing folder root
+    #[clap(short, long, value_parser)]
+    absolute: bool,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct TidyArgs {
+    /// Sort patterns within groups separated by comments, blank lines and #includes
+    ///
+    /// Groups with negated patterns or with both (?d) and regular patterns
+    /// are left as they are, since their order matters.
+    #[clap(long, arg_enum, value_parser, value_name = "ORDER", default_value_t = tidy::SortOrder::None)]
+    sort: tidy::SortOrder,
+
+    /// Only report whether the file needs tidying, don't change it
+    #[clap(long, value_parser)]
+    check: bool,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct EditArgs {
+    /// Check the file for syntax errors after the editor exits
+    #[clap(long, value_parser)]
+    validate: bool,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct ExportArgs {
+    /// Replace names with placeholders, so the file can be shared in bug reports
+    ///
+    /// Modifiers, wildcards, directory structure and file extensions are preserved,
+    /// comments are replaced with "// comment"
+    #[clap(long, value_parser)]
+    sanitize: bool,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+struct CompletionsArgs {
+    /// Shell to generate completions for, detected from $SHELL if omitted
+    #[clap(value_parser)]
+    shell: Option<Shell>,
+
+    /// Write completion script into the directory the shell loads completions from
+    #[clap(short, long, value_parser)]
+    install: bool,
+
+    /// Only display where the completion script would be installed
+    #[clap(long, value_parser, requires("install"))]
+    dry_run: bool,
+}
+
+impl Args {
+    fn quiet(&self) -> bool {
+        self.silent || self.json
+    }
+
+    /// Storage of ignore files, picked by --backend
+    fn backend(&self) -> &'static dyn Backend {
+        match (self.backend, self.fsync) {
+            (BackendKind::Memory, _) => memory_backend(),
+            (BackendKind::Local, true) => &backend::Local { fsync: true },
+            (BackendKind::Local, false) => &backend::Local { fsync: false },
+        }
+    }
+}
+
+/// Machine-readable summary of an invocation, printed with `--json`
+#[derive(Serialize, Default, Debug)]
+struct Report {
+    folder: Option<PathBuf>,
+    target: Option<PathBuf>,
+    /// Files patterns are also added to, with --target both
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    other_targets: Vec<PathBuf>,
+    patterns: Vec<String>,
+    skipped: Vec<String>,
+    warnings: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Exit codes, so scripts can tell failures apart
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Status {
+    Success = 0,
+    Error = 1,
+    // 2 is used by clap for invalid arguments
+    NotInFolder = 3,
+    InvalidPattern = 4,
+    Io = 5,
+    Aborted = 6,
+    NothingToDo = 7,
+}
+
+/// Errors that have their own exit code
+#[derive(Debug)]
+enum Failure {
+    NotInFolder,
+    InsideInternalDir {
+        root: PathBuf,
+        dir: String,
+    },
+    InvalidPatterns(Vec<String>),
+    InvalidFile {
+        path: PathBuf,
+        problems: usize,
+    },
+    ReadOnly(PathBuf),
+    NotWritable {
+        path: PathBuf,
+        problem: String,
+    },
+    Corrupt {
+        path: PathBuf,
+        damage: corrupt::Damage,
+    },
+    Problems(usize),
+    Policy {
+        root: PathBuf,
+        violations: Vec<String>,
+    },
+    NothingToDo,
+    NotFound {
+        path: PathBuf,
+        patterns: Vec<String>,
+    },
+    Aborted,
+    NotInteractive(String),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInFolder => write!(f, "Current directory is not inside of a syncthing folder"),
+            Self::InsideInternalDir { root, dir } => write!(
+                f,
+                "Current directory is inside of syncthing's internal {dir} directory, \
+                patterns for it are meaningless. Run stignore from {} instead",
+                root.display()
+            ),
+            Self::InvalidPatterns(errs) => write!(
+                f,
+                "Incorrect pattern{}:\n{}",
+                if errs.len() > 1 { "s" } else { "" },
+                errs.join("\n")
+            ),
+            Self::InvalidFile { path, problems } => write!(
+                f,
+                "{} has {problems} problem{}",
+                path.display(),
+                if *problems > 1 { "s" } else { "" }
+            ),
+            Self::ReadOnly(path) => write!(
+                f,
+                "{} is on a read-only file system, no changes were made",
+                path.display()
+            ),
+            Self::NotWritable { path, problem } => write!(
+                f,
+                "{} can't be modified: {problem}. No changes were made, \
+                pass --stdout to print the patterns instead",
+                path.display()
+            ),
+            Self::Corrupt { path, damage } => write!(
+                f,
+                "{} looks corrupted: {damage}. Refusing to work with it",
+                path.display()
+            ),
+            Self::Problems(problems) => write!(
+                f,
+                "Found {problems} problem{}",
+                if *problems > 1 { "s" } else { "" }
+            ),
+            Self::Policy { root, violations } => write!(
+                f,
+                "The change isn't allowed by {}:\n{}",
+                root.join(policy::FILE).display(),
+                violations.join("\n")
+            ),
+            Self::NothingToDo => write!(f, "No patterns supplied!"),
+            Self::NotFound { path, patterns } => write!(
+                f,
+                "Pattern{} not found in {}:\n{}",
+                if patterns.len() > 1 { "s" } else { "" },
+                path.display(),
+                patterns.join("\n")
+            ),
+            Self::Aborted => write!(f, "Aborted by user"),
+            Self::NotInteractive(question) => write!(
+                f,
+                "Can't ask \"{question}\", not running in a terminal. \
+                Use --yes to proceed without confirmation \
+                or --non-interactive dry-run to only display planned changes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+impl Status {
+    fn of(err: &anyhow::Error) -> Self {
+        if let Some(failure) = err.chain().find_map(|e| e.downcast_ref::<Failure>()) {
+            return match failure {
+                Failure::NotInFolder | Failure::InsideInternalDir { .. } => Self::NotInFolder,
+                Failure::InvalidPatterns(_)
+                | Failure::InvalidFile { .. }
+                | Failure::Problems(_)
+                | Failure::Policy { .. } => Self::InvalidPattern,
+                Failure::NothingToDo | Failure::NotFound { .. } => Self::NothingToDo,
+                Failure::ReadOnly(_) | Failure::NotWritable { .. } | Failure::Corrupt { .. } => {
+                    Self::Io
+                }
+                Failure::Aborted | Failure::NotInteractive(_) => Self::Aborted,
+            };
+        }
+        if err.chain().any(|e| e.is::<std::io::Error>()) {
+            return Self::Io;
+        }
+        Self::Error
+    }
+}
+
+#[cfg(windows)]
+const LINE_ENDING: &str = "\r\n";
+#[cfg(not(windows))]
+const LINE_ENDING: &str = "\n";
+
+/// Patterns of the folder `st_dir`, with its markers treated as internal files
+fn load_matcher(args: &Args, st_dir: &Path) -> Result<matcher::Matcher> {
+    Ok(matcher::Matcher::load(args.backend(), st_dir)?.with_markers(markers(args)))
+}
+
+/// Names of folder markers: --marker, or `.stfolder` and custom names of
+/// folders from syncthing's config.xml
+fn markers(args: &Args) -> &[String] {
+    args.markers.get_or_init(|| {
+        if let Some(marker) = &args.marker {
+            return vec![marker.clone()];
+        }
+        let mut markers = vec![".stfolder".to_string()];
+        if args.fast_append {
+            return markers;
+        }
+        if let Ok(config) = syncthing::Config::load() {
+            for folder in config.folders {
+                if !markers.contains(&folder.marker) {
+                    markers.push(folder.marker);
+                }
+            }
+        }
+        markers
+    })
+}
+
+/// Is `dir` the root of a syncthing folder: contains one of the `markers`.
+/// The default `.stfolder` has to be a directory, custom markers can be files
+fn has_marker(dir: &Path, markers: &[String]) -> bool {
+    markers.iter().any(|m| {
+        let marker = dir.join(m);
+        debug!("Looking for {}", marker.display());
+        if m == ".stfolder" {
+            marker.is_dir()
+        } else {
+            marker.exists()
+        }
+    })
+}
+
+/// Parents of `dir` (and `dir` itself) that contain one of the `markers`, closest first
+fn find_folders(dir: &Path, markers: &[String]) -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    let mut st_dir = dir.to_path_buf();
+    loop {
+        if has_marker(&st_dir, markers) {
+            folders.push(st_dir.clone());
+        }
+        if !st_dir.pop() {
+            return folders;
+        }
+    }
+}
+
+/// Syncthing folder that `dir` is in. Of nested folders the closest one is
+/// picked, unless --outermost is passed or the user picks another one
+fn find_folder(args: &Args, dir: &Path, interactive: bool) -> Result<Option<PathBuf>> {
+    let mut folders = find_folders(dir, markers(args));
+    if folders.len() < 2 || args.innermost || args.fast_append {
+        return Ok(folders.into_iter().next());
+    }
+    if args.outermost {
+        return Ok(folders.pop());
+    }
+    if !interactive || !is_interactive() {
+        if !args.quiet() {
+            let note = format!(
+                "{} is inside of {} nested syncthing folders, using the closest one. \
+                Pass --innermost or --outermost to pick one explicitly",
+                dir.display(),
+                folders.len()
+            );
+            eprintln!(
+                "{} {note}",
+                color::Palette::stderr(args.color).note("NOTE:")
+            );
+        }
+        return Ok(folders.into_iter().next());
+    }
+
+    use question::{Answer, Question};
+    println!("{} is inside of nested syncthing folders:", dir.display());
+    for (n, folder) in folders.iter().enumerate() {
+        println!("{:>3}) {}", n + 1, folder.display());
+    }
+    let question = format!("Folder to work with (1-{}):", folders.len());
+    loop {
+        let Some(Answer::RESPONSE(answer)) = Question::new(&question).ask() else {
+            bail!(Failure::Aborted);
+        };
+        if answer.trim().is_empty() {
+            bail!(Failure::Aborted);
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=folders.len()).contains(&n) => {
+                return Ok(Some(folders.swap_remove(n - 1)))
+            }
+            _ => println!("Enter a number from 1 to {}", folders.len()),
+        }
+    }
+}
+
+/// Current directory, with symlinks resolved unless --no-resolve-symlinks is passed
+fn current_dir(args: &Args) -> Result<PathBuf> {
+    let cwd = std::env::current_dir().context("Can't determine current working directory")?;
+    if !args.no_resolve_symlinks {
+        return winpath::canonicalize(&cwd).context("Can't determine current working directory");
+    }
+    // $PWD is the path the shell followed to get here, unless it's stale
+    let same = |a: &Path, b: &Path| matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b);
+    match std::env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && same(&pwd, &cwd) => Ok(normalize(&pwd)),
+        _ => Ok(cwd),
+    }
+}
+
+/// Absolute path of directory `dir`, with symlinks resolved unless
+/// --no-resolve-symlinks is passed
+fn resolve_dir(args: &Args, dir: &Path) -> Result<PathBuf> {
+    if args.no_resolve_symlinks && dir.is_dir() {
+        return Ok(normalize(&current_dir(args)?.join(dir)));
+    }
+    winpath::canonicalize(dir).with_context(|| format!("Can't find directory {}", dir.display()))
+}
+
+fn find_syncthing_dir(args: &Args) -> Result<(PathBuf, PathBuf)> {
+    let cwd = match &args.relative_to {
+        Some(dir) => resolve_dir(args, dir)?,
+        None => current_dir(args)?,
+    };
+    let root = match &args.root {
+        Some(root) => Some(resolve_dir(args, root)?),
+        None => find_folder(args, &cwd, true)?,
+    };
+    if let Some(root) = root.as_ref().filter(|r| !cwd.starts_with(r)) {
+        // outside of the folder patterns are relative to its root
+        info!("Using syncthing folder {}", root.display());
+        return Ok((
+            root.clone(),
+            PathBuf::from(path::Component::RootDir.as_os_str()),
+        ));
+    }
+    let st_dir = match root {
+        Some(st_dir) => st_dir,
+        None if args.on_missing_folder == OnMissingFolder::CreateMarker => {
+            create_marker(args, &cwd)?;
+            cwd.clone()
+        }
+        None if args.on_missing_folder == OnMissingFolder::Choose => {
+            let st_dir = choose_folder()?;
+            info!("Chose syncthing folder {}", st_dir.display());
+            return Ok((st_dir, PathBuf::from(path::Component::RootDir.as_os_str())));
+        }
+        None => bail!(Failure::NotInFolder),
+    };
+    info!("Found syncthing folder {}", st_dir.display());
+
+    let relative = cwd.strip_prefix(&st_dir).unwrap();
+    if let Some(path::Component::Normal(dir)) = relative.components().next() {
+        if dir == ".stversions" || markers(args).iter().any(|m| dir == m.as_str()) {
+            bail!(Failure::InsideInternalDir {
+                root: st_dir,
+                dir: dir.to_string_lossy().into_owned(),
+            });
+        }
+    }
+
+    let prefix = path::Path::join(
+        path::Path::new(path::Component::RootDir.as_os_str()),
+        relative,
+    );
+
+    Ok((st_dir, prefix))
+}
+
+/// Prepends `prepend_prefix` to patterns and adds `modifiers` to them.
+/// Modifiers of patterns are put in the `(?d)(?i)!` order when `modifiers` are added
+fn process_patterns(
+    patterns: &[String],
+    prepend_prefix: Option<&PathBuf>,
+    modifiers: syntax::Modifiers,
+) -> Result<String> {
+    let mut out_str = String::new();
+    let mut errs = Vec::new();
+
+    let patterns = patterns.iter().flat_map(|t| t.split('\n'));
+
+    for mut pattern in patterns {
+        pattern = pattern.trim();
+        if pattern.is_empty() || pattern.starts_with("//") {
+            // empty pattern results in an extra new line inserted
+            out_str.push_str(pattern);
+            out_str.push_str(LINE_ENDING);
+            continue;
+        }
+
+        let pattern_path = if let Some(file) = pattern.strip_prefix("#include ") {
+            // includes can't have modifiers
+            if !modifiers.is_empty() {
+                errs.push(format!("{pattern}: #include can't have modifiers"));
+                continue;
+            }
+            out_str.push_str("#include ");
+            file.trim_start()
+        } else {
+            match syntax::parse_modifiers(pattern) {
+                Ok((own, pattern_path)) => {
+                    // deduplicated and in the canonical order
+                    out_str.push_str(&own.union(modifiers).to_string());
+                    pattern_path
+                }
+                Err(problem) => {
+                    errs.push(format!("{pattern}: {problem}"));
+                    continue;
+                }
+            }
+        };
+
+        match prepend_prefix {
+            // included files are relative to the ignore file, not the folder root
+            None if pattern.starts_with("#include") => {
+                out_str.push_str(pattern_path);
+            }
+            None => match syntax::normalize(pattern_path) {
+                Some(normalized) => out_str.push_str(&normalized),
+                None => {
+                    errs.push(format!("{pattern}: points outside of the syncthing folder"));
+                    continue;
+                }
+            },
+            Some(prefix) => {
+                let pattern_path = Path::new(pattern_path);
+                let mut joined = PathBuf::new();
+                let mut escapes = false;
+                // names of directories are literal, `foo [old]` isn't a glob
+                let mut literal_prefix = PathBuf::new();
+                for component in prefix.components() {
+                    match component {
+                        path::Component::Normal(name) => {
+                            literal_prefix.push(syntax::escape(winpath::utf8(name)?));
+                        }
+                        component => literal_prefix.push(component),
+                    }
+                }
+                for component in
+                    literal_prefix
+                        .components()
+                        .chain(pattern_path.components().skip_while(|m| {
+                            matches!(
+                                m,
+                                path::Component::RootDir
+                                    | path::Component::Prefix(_)
+                                    | path::Component::CurDir
+                            )
+                        }))
+                {
+                    // `..` is resolved against the prefix, syncthing never matches it
+                    if component == path::Component::ParentDir {
+                        escapes |= !joined.pop();
+                    } else {
+                        joined.push(component);
+                    }
+                }
+                if escapes {
+                    errs.push(format!("{pattern}: points outside of the syncthing folder"));
+                    continue;
+                }
+                out_str.push_str(&winpath::to_pattern(&joined)?);
+                // components drop the trailing `/`, keep it like `normalize` does
+                if pattern_path.as_os_str().to_string_lossy().ends_with('/')
+                    && !out_str.ends_with('/')
+                {
+                    out_str.push('/');
+                }
+            }
+        }
+        out_str.push_str(LINE_ENDING);
+    }
+
+    if !errs.is_empty() {
+        bail!(Failure::InvalidPatterns(errs));
+    }
+    if out_str.trim().is_empty() {
+        bail!(Failure::NothingToDo)
+    }
+    Ok(out_str)
+}
+
+/// Contents of the ignore file at `path`, `None` if it doesn't exist.
+/// Fails if the file looks like binary garbage
+fn read_ignore_file(backend: &dyn Backend, path: &Path) -> Result<Option<String>> {
+    match backend
+        .read(path)
+        .with_context(|| format!("Can't read {}", path.display()))?
+    {
+        Some(contents) => corrupt::decode(path, contents).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn is_stignore_sync_included(backend: &dyn Backend, stignore: &Path) -> Result<bool> {
+    let re = Regex::new(r"^\s*#include\s+\.stignore_sync\s*$").unwrap();
+    let contents = read_ignore_file(backend, stignore)?.unwrap_or_default();
+    let line = contents.lines().position(|l| re.is_match(l)).map(|n| n + 1);
+    if let Some(n) = line {
+        info!("{}:{n} includes .stignore_sync", stignore.display());
+        return Ok(true);
+    }
+    // .stignore -> common.stignore -> .stignore_sync
+    let root = stignore.parent().unwrap_or(Path::new("."));
+    let nested = is_reachable(backend, root, &root.join(".stignore_sync"));
+    if nested {
+        info!(
+            "{} includes .stignore_sync through other files",
+            stignore.display()
+        );
+    } else {
+        info!("{} doesn't include .stignore_sync", stignore.display());
+    }
+    Ok(nested)
+}
+
+/// Path in the folder `st_dir` that the processed `line` names, if it's a
+/// pattern anchored to the root without glob syntax
+fn named_path(st_dir: &Path, line: &str) -> Option<PathBuf> {
+    let line = line.trim();
+    if line.starts_with("//") {
+        return None;
+    }
+    let (modifiers, pattern) = syntax::split_modifiers(line);
+    let path = pattern
+        .strip_prefix('/')?
+        .trim_end_matches("/**")
+        .trim_end_matches('/');
+    if modifiers.contains("(?i)") || path.is_empty() || !syntax::is_literal(path) {
+        return None;
+    }
+    Some(st_dir.join(path))
+}
+
+/// Processed `patterns` rewritten to ignore directories in the `idiom`.
+/// Patterns naming existing files in the folder `st_dir` are kept
+fn dir_idiom(
+    backend: &dyn Backend,
+    st_dir: &Path,
+    patterns: &str,
+    idiom: lint::DirIdiom,
+) -> String {
+    let mut out = String::new();
+    for line in patterns.lines() {
+        let (modifiers, pattern) = syntax::split_modifiers(line.trim());
+        let dir = pattern.trim_end_matches("/**").trim_end_matches('/');
+        let file = named_path(st_dir, line).is_some_and(|p| backend.is_file(&p));
+        if line.trim().starts_with("//") || pattern.starts_with('#') || dir.is_empty() || file {
+            out.push_str(line);
+        } else {
+            out.push_str(modifiers);
+            out.push_str(dir);
+            if idiom == lint::DirIdiom::Contents {
+                out.push_str("/**");
+            }
+        }
+        out.push_str(LINE_ENDING);
+    }
+    out
+}
+
+/// Warns about processed `patterns` naming paths that don't exist in the
+/// folder `st_dir`, most likely typos
+fn warn_about_missing(args: &Args, st_dir: &Path, patterns: &str, report: &mut Report) {
+    for line in patterns.lines().map(str::trim) {
+        if syntax::split_modifiers(line).0.contains('!') {
+            continue;
+        }
+        let Some(path) = named_path(st_dir, line) else {
+            continue;
+        };
+        if std::fs::symlink_metadata(&path).is_ok() {
+            continue;
+        }
+        let note = format!(
+            "{line}: {} doesn't exist, check it for typos. Pass --no-check to skip this check",
+            path.display()
+        );
+        if !args.quiet() {
+            eprintln!(
+                "{} {note}",
+                color::Palette::stderr(args.color).note("NOTE:")
+            );
+        }
+        report.warnings.push(note);
+    }
+}
+
+/// `text` in the normalization `form`
+fn normalize_unicode(form: UnicodeForm, text: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    match form {
+        UnicodeForm::Auto if cfg!(target_os = "macos") => text.nfd().collect(),
+        UnicodeForm::Nfd => text.nfd().collect(),
+        UnicodeForm::Nfc => text.nfc().collect(),
+        UnicodeForm::Auto | UnicodeForm::None => text.to_string(),
+    }
+}
+
+/// `path` with `.` and `..` resolved without looking at the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    path.components().fold(PathBuf::new(), |mut out, c| {
+        match c {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+        out
+    })
+}
+
+/// Is the ignore file `path` used by syncthing: it's `.stignore` of `root` or
+/// is included from it. The file itself doesn't have to exist
+fn is_reachable(backend: &dyn Backend, root: &Path, path: &Path) -> bool {
+    let path = normalize(path);
+    ignore_files(backend, root).iter().any(|file| {
+        let dir = file.parent().unwrap_or(root);
+        normalize(file) == path
+            || corrupt::read(backend, file)
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|l| l.trim().strip_prefix("#include"))
+                .any(|include| normalize(&dir.join(include.trim())) == path)
+    })
+}
+
+/// Line ending that the first line of `contents` uses, `LINE_ENDING` if
+/// there are no complete lines yet
+fn line_ending(contents: &[u8]) -> &'static str {
+    match contents.iter().position(|&b| b == b'\n') {
+        Some(n) if n > 0 && contents[n - 1] == b'\r' => "\r\n",
+        Some(_) => "\n",
+        None => LINE_ENDING,
+    }
+}
+
+/// `text` with every line ending replaced by `ending`
+fn with_line_ending(text: &str, ending: &str) -> String {
+    text.split_inclusive('\n')
+        .map(|l| match l.strip_suffix('\n') {
+            Some(line) => format!("{}{ending}", line.strip_suffix('\r').unwrap_or(line)),
+            None => l.to_string(),
+        })
+        .collect()
+}
+
+/// Appends `patterns` to the file at `path`, making sure that they start on a
+/// new line and use the same line endings as the rest of the file
+fn append(backend: &dyn Backend, path: &Path, patterns: &str) -> Result<()> {
+    let contents = backend.read(path)?.unwrap_or_default();
+    let contents = contents
+        .strip_prefix(corrupt::BOM.as_bytes())
+        .unwrap_or(&contents);
+    let ending = line_ending(contents);
+    let mut data = String::new();
+    if !contents.is_empty() && !contents.ends_with(b"\n") {
+        debug!("{} doesn't end with a new line, adding it", path.display());
+        data.push_str(ending);
+    }
+    data.push_str(&with_line_ending(patterns, ending));
+    backend.append(path, data.as_bytes())?;
+    Ok(())
+}
+
+/// Line of `contents` that --prepend and --before-includes insert patterns
+/// before, `None` if they have to be appended
+fn insertion_line(contents: &str, before_includes: bool) -> Option<usize> {
+    if !before_includes {
+        return Some(0);
+    }
+    contents
+        .lines()
+        .position(|l| l.trim().starts_with("#include"))
+}
+
+/// `contents` with `patterns` inserted before line `at`
+fn insert_at(contents: &str, at: usize, patterns: &str) -> String {
+    let mut out = String::new();
+    for (n, line) in contents.split_inclusive('\n').enumerate() {
+        if n == at {
+            out.push_str(patterns);
+        }
+        out.push_str(line);
+    }
+    if contents.split_inclusive('\n').count() <= at {
+        out.push_str(patterns);
+    }
+    out
+}
+
+/// Is the folder `st_dir` on a case-insensitive file system: its marker can
+/// be found by an upper case name
+fn is_case_insensitive_fs(args: &Args, st_dir: &Path) -> bool {
+    // markers without letters can't tell
+    let probe = markers(args).iter().find_map(|name| {
+        let upper = name.to_uppercase();
+        let marker = std::fs::metadata(st_dir.join(name)).ok()?;
+        (upper != *name).then_some((marker, upper))
+    });
+    let Some((marker, upper)) = probe else {
+        debug!(
+            "{} has no marker to probe the case sensitivity with",
+            st_dir.display()
+        );
+        return false;
+    };
+    let Ok(upper) = std::fs::metadata(st_dir.join(upper)) else {
+        info!("{} is on a case-sensitive file system", st_dir.display());
+        return false;
+    };
+    #[cfg(unix)]
+    let same = {
+        use std::os::unix::fs::MetadataExt;
+        (marker.dev(), marker.ino()) == (upper.dev(), upper.ino())
+    };
+    #[cfg(not(unix))]
+    let same = marker.is_dir() == upper.is_dir();
+    if same {
+        info!("{} is on a case-insensitive file system", st_dir.display());
+    }
+    same
+}
+
+/// Is `path` located on a file system mounted read-only (snapshots, read-only bind mounts)
+#[cfg(unix)]
+fn is_read_only_mount(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is a valid C string and stat is a valid statvfs struct
+    let res = unsafe { libc::statvfs(path.as_ptr(), &mut stat) };
+    res == 0 && stat.f_flag & libc::ST_RDONLY != 0
+}
+
+#[cfg(not(unix))]
+fn is_read_only_mount(_path: &Path) -> bool {
+    false
+}
+
+/// Can this process write to the existing `path`
+#[cfg(unix)]
+fn is_writable(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return true;
+    };
+    // SAFETY: path is a valid C string
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_writable(path: &Path) -> bool {
+    std::fs::metadata(path).map_or(true, |m| !m.permissions().readonly())
+}
+
+/// Why the ignore file at `path` can't be changed and how to fix that, `None`
+/// if it can. Rewritten and new files also need a writable directory
+fn write_problem(path: &Path, rewrite: bool) -> Option<String> {
+    let exists = path.exists();
+    let (target, what) = if exists && !is_writable(path) {
+        (path, "it")
+    } else {
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty())?;
+        if (exists && !rewrite) || is_writable(dir) {
+            return None;
+        }
+        (dir, "its directory")
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let owner = std::fs::metadata(target).ok()?.uid();
+        // SAFETY: geteuid has no preconditions
+        if owner != unsafe { libc::geteuid() } {
+            return Some(format!(
+                "{what} belongs to another user (uid {owner}). Ask them to change it, \
+                take it over with `sudo chown $USER {}`, or check permissions of \
+                the share if the folder is on a NAS",
+                target.display()
+            ));
+        }
+        Some(format!(
+            "{what} is read-only. Make it writable with `chmod u+w {}`",
+            target.display()
+        ))
+    }
+    #[cfg(not(unix))]
+    Some(format!(
+        "{what} is read-only. Clear the Read-only attribute in its Properties \
+        or with `attrib -r {}`",
+        target.display()
+    ))
+}
+
+fn home_dir() -> Result<PathBuf> {
+    #[cfg(windows)]
+    let home = std::env::var_os("USERPROFILE");
+    #[cfg(not(windows))]
+    let home = std::env::var_os("HOME");
+    home.filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .context("Can't determine home directory")
+}
+
+/// `$var` if it is set to an absolute path, `~/default` otherwise
+fn xdg_dir(var: &str, default: &str) -> Result<PathBuf> {
+    match std::env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir),
+        _ => Ok(home_dir()?.join(default)),
+    }
+}
+
+fn detect_shell() -> Result<Shell> {
+    if let Some(shell) = std::env::var_os("SHELL") {
+        let name = Path::new(&shell)
+            .file_stem()
+            .map(|n| n.to_string_lossy().to_lowercase());
+        return match name.as_deref() {
+            Some("bash") => Ok(Shell::Bash),
+            Some("zsh") => Ok(Shell::Zsh),
+            Some("fish") => Ok(Shell::Fish),
+            Some("elvish") => Ok(Shell::Elvish),
+            Some("pwsh" | "powershell") => Ok(Shell::PowerShell),
+            _ => bail!(
+                "Unsupported shell {}, specify one explicitly",
+                Path::new(&shell).display()
+            ),
+        };
+    }
+    if cfg!(windows) {
+        return Ok(Shell::PowerShell);
+    }
+    bail!("Can't detect the shell ($SHELL is not set), specify one explicitly")
+}
+
+/// Runs `program args...` and returns its trimmed stdout, if it succeeded
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Process::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Short name of this machine
+fn host_name() -> Option<String> {
+    command_output("hostname", &[])
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|h| h.split('.').next().unwrap_or_default().to_string())
+}
+
+fn user_name() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+}
+
+/// Current time as `YYYY-MM-DD HH:MM UTC`
+fn utc_timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, time) = (secs / 86400, secs % 86400);
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60
+    )
+}
+
+/// Start of comments written by --provenance
+const PROVENANCE: &str = "// Added ";
+
+/// `// Added <date> by <user>@<host>` comment for --provenance
+fn provenance() -> String {
+    let user = user_name().unwrap_or_else(|| "unknown".to_string());
+    let host = host_name().unwrap_or_else(|| "unknown".to_string());
+    format!("{PROVENANCE}{} by {user}@{host}", utc_timestamp())
+}
+
+/// Where the completion script has to be installed
+struct CompletionsLocation {
+    script: PathBuf,
+    /// Line that has to be present in `rc_file` for the script to be loaded
+    rc_line: Option<(PathBuf, String)>,
+    /// Manual steps required to activate the completions
+    hint: Option<String>,
+}
+
+fn completions_location(shell: Shell) -> Result<CompletionsLocation> {
+    Ok(match shell {
+        Shell::Bash => {
+            let dir = match std::env::var_os("BASH_COMPLETION_USER_DIR") {
+                Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+                _ => xdg_dir("XDG_DATA_HOME", ".local/share")?.join("bash-completion"),
+            };
+            CompletionsLocation {
+                script: dir.join("completions").join("stignore"),
+                rc_line: None,
+                hint: Some("Completions are loaded by the bash-completion package".to_string()),
+            }
+        }
+        Shell::Zsh => {
+            let home = home_dir()?;
+            // fpath isn't exported, ask an interactive zsh for it
+            let fpath = command_output("zsh", &["-ic", "print -rl -- $fpath"]).unwrap_or_default();
+            let user_dir = fpath.lines().map(PathBuf::from).find(|dir| {
+                dir.starts_with(&home)
+                    && dir.is_dir()
+                    && !std::fs::metadata(dir).map_or(true, |m| m.permissions().readonly())
+            });
+            match user_dir {
+                Some(dir) => CompletionsLocation {
+                    script: dir.join("_stignore"),
+                    rc_line: None,
+                    hint: None,
+                },
+                None => {
+                    let dir = home.join(".zfunc");
+                    CompletionsLocation {
+                        script: dir.join("_stignore"),
+                        rc_line: None,
+                        hint: Some(format!(
+                            "{} is not in your $fpath, add following lines to ~/.zshrc:\n\
+                            fpath=({} $fpath)\n\
+                            autoload -Uz compinit && compinit",
+                            dir.display(),
+                            dir.display()
+                        )),
+                    }
+                }
+            }
+        }
+        Shell::Fish => CompletionsLocation {
+            script: xdg_dir("XDG_CONFIG_HOME", ".config")?
+                .join("fish")
+                .join("completions")
+                .join("stignore.fish"),
+            rc_line: None,
+            hint: None,
+        },
+        Shell::PowerShell => {
+            let profile = ["pwsh", "powershell"]
+                .iter()
+                .find_map(|ps| command_output(ps, &["-NoProfile", "-Command", "$PROFILE"]))
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .context("Can't determine PowerShell profile location")?;
+            let script = profile
+                .parent()
+                .context("Can't determine PowerShell profile location")?
+                .join("stignore.ps1");
+            let rc_line = format!(". \"{}\"", script.display());
+            CompletionsLocation {
+                script,
+                rc_line: Some((profile, rc_line)),
+                hint: None,
+            }
+        }
+        _ => bail!(
+            "Don't know where {shell} loads completions from, \
+            redirect the output of `stignore completions {shell}` manually"
+        ),
+    })
+}
+
+/// Makes completion `script` ask stignore for patterns of `remove`
+fn add_dynamic_completions(shell: Shell, script: &mut Vec<u8>) {
+    let snippet = match shell {
+        Shell::Bash => {
+            r#"
+_stignore_dynamic() {
+    if [[ "${COMP_WORDS[1]}" == remove && "${COMP_WORDS[COMP_CWORD]}" != -* ]]; then
+        local IFS=$'\n'
+        COMPREPLY=($(stignore completion-candidates remove -- "${COMP_WORDS[COMP_CWORD]}" 2>/dev/null))
+        return
+    fi
+    _stignore "$@"
+}
+
+complete -F _stignore_dynamic -o bashdefault -o default stignore
+"#
+        }
+        Shell::Zsh => {
+            // replaces the call of _stignore at the end of the script
+            let end = b"_stignore \"$@\"\n";
+            if script.ends_with(end) {
+                script.truncate(script.len() - end.len());
+            }
+            r#"_stignore_dynamic() {
+    if [[ "${words[2]}" == remove && "$PREFIX" != -* ]]; then
+        local -a patterns
+        patterns=("${(@f)$(stignore completion-candidates remove -- "$PREFIX" 2>/dev/null)}")
+        compadd -Q -a patterns
+        return
+    fi
+    _stignore "$@"
+}
+
+_stignore_dynamic "$@"
+"#
+        }
+        Shell::Fish => {
+            r#"
+complete -c stignore -n "__fish_seen_subcommand_from remove" -f -a "(stignore completion-candidates remove -- (commandline -ct) 2>/dev/null)"
+"#
+        }
+        _ => return,
+    };
+    script.extend_from_slice(snippet.as_bytes());
+}
+
+fn completions(args: &CompletionsArgs) -> Result<()> {
+    let shell = match args.shell {
+        Some(shell) => shell,
+        None => detect_shell()?,
+    };
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Args::command(), "stignore", &mut script);
+    add_dynamic_completions(shell, &mut script);
+
+    if !args.install {
+        std::io::stdout().write_all(&script)?;
+        return Ok(());
+    }
+
+    let location = completions_location(shell)?;
+    let rc_update = match location.rc_line {
+        Some((ref rc_file, ref line)) => {
+            let rc = match std::fs::read_to_string(rc_file) {
+                Ok(rc) => rc,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Can't read {}", rc_file.display()))
+                }
+            };
+            if rc.lines().any(|l| l.trim() == line) {
+                None
+            } else {
+                Some((rc_file, line))
+            }
+        }
+        None => None,
+    };
+
+    if args.dry_run {
+        println!(
+            "Would write {shell} completions to {}",
+            location.script.display()
+        );
+        if let Some((rc_file, line)) = rc_update {
+            println!("Would append `{line}` to {}", rc_file.display());
+        }
+    } else {
+        if let Some(dir) = location.script.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Can't create {}", dir.display()))?;
+        }
+        std::fs::write(&location.script, &script)
+            .with_context(|| format!("Can't write {}", location.script.display()))?;
+        println!(
+            "Installed {shell} completions to {}",
+            location.script.display()
+        );
+        if let Some((rc_file, line)) = rc_update {
+            let mut rc = File::options()
+                .create(true)
+                .append(true)
+                .open(rc_file)
+                .with_context(|| format!("Can't open {}", rc_file.display()))?;
+            write!(rc, "{LINE_ENDING}{line}{LINE_ENDING}")?;
+            println!("Added `{line}` to {}", rc_file.display());
+        }
+    }
+    if let Some(hint) = location.hint {
+        println!("{hint}");
+    }
+    Ok(())
+}
+
+/// Turns `dir` into a syncthing folder root (the way syncthing does when
+/// the folder is added) by creating `.stfolder` (or --marker) in it
+fn create_marker(args: &Args, dir: &Path) -> Result<()> {
+    let name = args.marker.as_deref().unwrap_or(".stfolder");
+    if !args.quiet() {
+        println!(
+            "{} isn't inside of a syncthing folder. Creating {name} in it makes \
+            stignore treat it as the root of one, so patterns will be relative to it. \
+            Add this directory itself to syncthing, otherwise the patterns won't \
+            match what you expect.",
+            dir.display()
+        );
+    }
+    let marker = dir.join(name);
+    confirm(args, &format!("Create {}?", marker.display()))?;
+    std::fs::create_dir(&marker).with_context(|| format!("Can't create {}", marker.display()))?;
+    info!("Created {}", marker.display());
+    Ok(())
+}
+
+/// Lets the user pick one of the folders from syncthing's config
+fn choose_folder() -> Result<PathBuf> {
+    use question::{Answer, Question};
+    if !is_interactive() {
+        bail!(Failure::NotInFolder);
+    }
+    let config = syncthing::Config::load()?;
+    if config.folders.is_empty() {
+        bail!(Failure::NotInFolder);
+    }
+    println!(
+        "Current directory is not inside of a syncthing folder. Folders from {}:",
+        config.path.display()
+    );
+    for (n, folder) in config.folders.iter().enumerate() {
+        let name = if folder.label.is_empty() {
+            &folder.id
+        } else {
+            &folder.label
+        };
+        println!("{:>3}) {name} ({})", n + 1, folder.path.display());
+    }
+    let question = format!("Folder to work with (1-{}):", config.folders.len());
+    loop {
+        let Some(Answer::RESPONSE(answer)) = Question::new(&question).ask() else {
+            bail!(Failure::Aborted);
+        };
+        if answer.trim().is_empty() {
+            bail!(Failure::Aborted);
+        }
+        let folder = answer
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| config.folders.get(n.checked_sub(1)?));
+        match folder {
+            Some(folder) if folder.path.is_dir() => return Ok(folder.path.clone()),
+            Some(folder) => println!("{} doesn't exist", folder.path.display()),
+            None => println!("Enter a number from 1 to {}", config.folders.len()),
+        }
+    }
+}
+
+/// Path of ignore `file` (relative to folder root `st_dir`), which has to be
+/// included from `.stignore`
+fn included_file(backend: &dyn Backend, st_dir: &Path, file: &Path) -> Result<PathBuf> {
+    let path = normalize(&st_dir.join(file));
+    if !is_reachable(backend, st_dir, &path) {
+        bail!(
+            "{} isn't included from {}, add `#include {}` to it first",
+            path.display(),
+            st_dir.join(".stignore").display(),
+            file.display()
+        );
+    }
+    read_ignore_file(backend, &path)?;
+    Ok(path)
+}
+
+/// Splits `patterns` between ignore files by routes from config files, the
+/// rest goes to the file selected by --target. Comments go with every part
+fn route_patterns(
+    args: &Args,
+    st_dir: &Path,
+    patterns: &str,
+    report: &mut Report,
+) -> Result<Vec<(PathBuf, String)>> {
+    let mut routes = Vec::new();
+    for route in &args.routes {
+        let rule = matcher::Rule::parse(&route.pattern)
+            .with_context(|| format!("Invalid route {:?}", route.pattern))?;
+        routes.push((rule, route.file.as_path()));
+    }
+    let mut comments = String::new();
+    let mut groups: Vec<(Option<&Path>, String)> = Vec::new();
+    for line in patterns.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with("//") {
+            comments.push_str(line);
+            comments.push_str(LINE_ENDING);
+            continue;
+        }
+        let (_, pattern) = syntax::split_modifiers(trimmed);
+        let path = pattern.trim_start_matches('/').trim_end_matches('/');
+        let file = routes
+            .iter()
+            .find(|(rule, _)| rule.matches(path))
+            .map(|(_, file)| *file);
+        match groups.iter_mut().find(|(f, _)| *f == file) {
+            Some((_, group)) => group.push_str(&format!("{line}{LINE_ENDING}")),
+            None => groups.push((file, format!("{line}{LINE_ENDING}"))),
+        }
+    }
+
+    let mut targets: Vec<(PathBuf, String)> = Vec::new();
+    for (file, group) in groups {
+        let path = match file {
+            Some(file) => {
+                let path = included_file(args.backend(), st_dir, file)?;
+                info!(
+                    "Routing {} to {}",
+                    group.trim().replace('\n', ", "),
+                    path.display()
+                );
+                path
+            }
+            None => resolve_target(args, st_dir, report)?,
+        };
+        match targets.iter_mut().find(|(t, _)| *t == path) {
+            Some((_, patterns)) => patterns.push_str(&group),
+            None => targets.push((path, format!("{comments}{group}"))),
+        }
+    }
+    if targets.is_empty() {
+        targets.push((resolve_target(args, st_dir, report)?, comments));
+    }
+    Ok(targets)
+}
+
+/// Picks the ignore file that `args.target` refers to
+fn resolve_target(args: &Args, st_dir: &Path, report: &mut Report) -> Result<PathBuf> {
+    let backend = args.backend();
+    let stignore = st_dir.join(".stignore");
+    let stignore_sync = st_dir.join(".stignore_sync");
+
+    read_ignore_file(backend, &stignore)?;
+    if let Some(file) = &args.file {
+        let path = included_file(backend, st_dir, file)?;
+        info!("Target: {}", path.display());
+        return Ok(path);
+    }
+    let resolved_target = if args.target == Target::Auto {
+        if is_stignore_sync_included(backend, &stignore)? {
+            Target::StignoreSync
+        } else {
+            if backend.exists(&stignore_sync) {
+                let note = ".stignore_sync exists, but wasn't included in .stignore. \
+                    Working with .stignore";
+                if !args.quiet() {
+                    eprintln!(
+                        "{} {note}",
+                        color::Palette::stderr(args.color).note("NOTE:")
+                    );
+                }
+                report.warnings.push(note.to_string());
+            }
+            Target::Stignore
+        }
+    } else {
+        args.target
+    };
+    info!(
+        "Target: {resolved_target:?}{}",
+        if args.target == Target::Auto {
+            " (resolved from auto)"
+        } else {
+            ""
+        }
+    );
+
+    Ok(match resolved_target {
+        Target::Stignore => stignore,
+        Target::StignoreSync => {
+            read_ignore_file(backend, &stignore_sync)?;
+            stignore_sync
+        }
+        Target::Both => bail!("--target both only works when adding patterns"),
+        Target::Auto => unreachable!("Target::Auto was resolved into concrete targets"),
+    })
+}
+
+fn export(args: &Args, export_args: &ExportArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let tgt_file = resolve_target(args, &st_dir, &mut Report::default())?;
+    let contents = read_ignore_file(args.backend(), &tgt_file)?.unwrap_or_default();
+
+    if export_args.sanitize {
+        let mut sanitizer = sanitize::Sanitizer::new();
+        let mut sanitized = String::new();
+        for line in contents.lines() {
+            sanitized.push_str(&sanitizer.line(line));
+            sanitized.push('\n');
+        }
+        page(args, &sanitized);
+    } else {
+        page(args, &contents);
+    }
+    Ok(())
+}
+
+/// Editor from $VISUAL or $EDITOR, the value can contain arguments
+fn editor() -> Process {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .map(|e| e.to_string_lossy().into_owned())
+        .find(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let mut parts = editor.split_whitespace();
+    let mut cmd = Process::new(parts.next().unwrap());
+    cmd.args(parts);
+    cmd
+}
+
+fn run_editor(path: &Path) -> Result<()> {
+    let mut cmd = editor();
+    debug!("Running {:?} {}", cmd.get_program(), path.display());
+    let status = cmd
+        .arg(path)
+        .status()
+        .with_context(|| format!("Can't start editor {:?}", cmd.get_program()))?;
+    if !status.success() {
+        bail!("Editor exited with {status}");
+    }
+    Ok(())
+}
+
+fn edit(args: &Args, edit_args: &EditArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let path = resolve_target(args, &st_dir, &mut Report::default())?;
+    let original = read_ignore_file(args.backend(), &path)?;
+    let original = original.as_deref();
+    // the editor changes a copy, the file is written through the backend
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let copy = std::env::temp_dir().join(format!("stignore-{}-{name}", std::process::id()));
+    let mut edited = original.unwrap_or_default().to_string();
+
+    loop {
+        let read_copy = std::fs::write(&copy, &edited)
+            .context("Can't create temporary file")
+            .and_then(|_| run_editor(&copy))
+            .and_then(|_| std::fs::read(&copy).context("Can't read edited file"));
+        let _ = std::fs::remove_file(&copy);
+        edited = corrupt::decode(&path, read_copy?)?;
+        let old = original.unwrap_or_default();
+        if let Err(err) = enforce_policy(args.backend(), &st_dir, &path, old, &edited) {
+            let again = is_interactive() && {
+                eprintln!("{err}");
+                ask("Edit again?")
+            };
+            if again {
+                continue;
+            }
+            info!("Keeping {} unchanged", path.display());
+            return Err(if is_interactive() {
+                Failure::Aborted.into()
+            } else {
+                err
+            });
+        }
+        let current = read_ignore_file(args.backend(), &path)?;
+        if current.as_deref() != Some(edited.as_str()) && (original.is_some() || !edited.is_empty())
+        {
+            rewrite(args.backend(), &path, &edited)?;
+        }
+        if !edit_args.validate {
+            return Ok(());
+        }
+        let problems = lint::check_syntax(args.backend(), &path)?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        let palette = color::Palette::stderr(args.color);
+        for problem in &problems {
+            eprintln!("{}", problem.colored(&palette));
+        }
+        // not using confirm(): with --yes it would reopen the editor forever
+        if !(is_interactive() && ask("Edit again?")) {
+            bail!(Failure::InvalidFile {
+                path,
+                problems: problems.len()
+            });
+        }
+    }
+}
+
+/// Prints `text`, piping it through the pager if stdout is a terminal.
+///
+/// Pager is taken from $STIGNORE_PAGER or $PAGER, `less` by default.
+/// Like git, `less` is started with `LESS=FRX`, so text that fits on the
+/// screen is just printed.
+fn page(args: &Args, text: &str) {
+    if args.no_pager || !std::io::stdout().is_terminal() {
+        print!("{text}");
+        return;
+    }
+    let pager = ["STIGNORE_PAGER", "PAGER"]
+        .iter()
+        .find_map(std::env::var_os)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "less".to_string());
+    let mut parts = pager.split_whitespace();
+    let program = match parts.next() {
+        Some(p) if p != "cat" => p,
+        _ => {
+            print!("{text}");
+            return;
+        }
+    };
+    debug!("Paging output through {program}");
+    let mut cmd = Process::new(program);
+    cmd.args(parts).stdin(std::process::Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(_) => {
+            print!("{text}");
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // pager might be closed before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let _ = child.wait();
+}
+
+/// Replaces contents of `path` in a way that never leaves it half-written
+fn rewrite(backend: &dyn Backend, path: &Path, contents: &str) -> Result<()> {
+    // keep line endings and the byte order mark of the existing file
+    let old = backend.read(path).ok().flatten().unwrap_or_default();
+    let contents = contents.strip_prefix(corrupt::BOM).unwrap_or(contents);
+    let mut contents = if old.contains(&b'\n') {
+        with_line_ending(contents, line_ending(&old))
+    } else {
+        contents.to_string()
+    };
+    if old.starts_with(corrupt::BOM.as_bytes()) {
+        contents.insert_str(0, corrupt::BOM);
+    }
+    backend
+        .write(path, contents.as_bytes())
+        .with_context(|| format!("Can't write {}", path.display()))
+}
+
+/// Fails if changing the ignore file `path` of the folder `st_dir` from `old`
+/// to `new` contents isn't allowed by the folder's policy
+fn enforce_policy(
+    backend: &dyn Backend,
+    st_dir: &Path,
+    path: &Path,
+    old: &str,
+    new: &str,
+) -> Result<()> {
+    let Some(policy) = policy::Policy::load(backend, st_dir)? else {
+        return Ok(());
+    };
+    let mut others = String::new();
+    for file in ignore_files(backend, st_dir).iter().filter(|f| *f != path) {
+        others.push_str(&corrupt::read(backend, file)?);
+        others.push('\n');
+    }
+    let violations = policy.check(old, new, &others);
+    if !violations.is_empty() {
+        bail!(Failure::Policy {
+            root: st_dir.to_path_buf(),
+            violations
+        });
+    }
+    Ok(())
+}
+
+/// `.stignore` of the folder `root` and all files included from it
+fn ignore_files(backend: &dyn Backend, root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut queue = std::collections::VecDeque::from([root.join(".stignore")]);
+    while let Some(file) = queue.pop_front() {
+        if files.contains(&file) {
+            continue;
+        }
+        let contents = match corrupt::read(backend, &file) {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("Skipping {}: {e}", file.display());
+                continue;
+            }
+        };
+        debug!("Read {}", file.display());
+        let dir = file.parent().unwrap_or(root);
+        for line in contents.lines() {
+            if let Some(include) = line.trim().strip_prefix("#include") {
+                let include = dir.join(include.trim());
+                info!("{} includes {}", file.display(), include.display());
+                queue.push_back(include);
+            }
+        }
+        files.push(file);
+    }
+    files
+}
+
+fn lint(args: &Args, lint_args: &LintArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let palette = color::Palette::stdout(args.color);
+    let mut problems = Vec::new();
+    let files = ignore_files(args.backend(), &st_dir);
+    let mut shadowed = match load_matcher(args, &st_dir) {
+        Ok(matcher) => lint::check_shadowing(matcher.rules()),
+        // syntax problems are reported below
+        Err(e) => {
+            debug!("Not checking for shadowed patterns: {e:#}");
+            Vec::new()
+        }
+    };
+    for file in &files {
+        let mut file_problems = lint::check_syntax(args.backend(), file)?;
+        let (own, rest): (Vec<_>, _) = shadowed.into_iter().partition(|p| &p.file == file);
+        shadowed = rest;
+        file_problems.extend(own);
+        if let Some(idiom) = lint_args.ensure_trailing_dir_rules {
+            file_problems.extend(lint::check_dir_rules(
+                args.backend(),
+                &st_dir,
+                file,
+                idiom,
+                markers(args),
+            )?);
+        }
+        if let Some(max) = lint_args.max_complexity {
+            file_problems.extend(lint::check_complexity(args.backend(), file, max)?);
+        }
+        file_problems.sort_by_key(|p| p.line);
+        problems.extend(file_problems);
+    }
+
+    if lint_args.fix {
+        for file in &files {
+            let fixable: Vec<_> = problems
+                .iter()
+                .filter(|p| &p.file == file && p.fix.is_some())
+                .collect();
+            if fixable.is_empty() {
+                continue;
+            }
+            let fixed = lint::fixed_contents(args.backend(), file, &problems)?;
+            enforce_policy(
+                args.backend(),
+                &st_dir,
+                file,
+                &corrupt::read(args.backend(), file)?,
+                &fixed,
+            )?;
+            rewrite(args.backend(), file, &fixed)?;
+            for problem in fixable {
+                println!(
+                    "{} {} -> {}",
+                    palette.location(&format!("{}:{}:", file.display(), problem.line)),
+                    palette.removed(problem.text.trim()),
+                    palette.added(problem.fix.as_ref().unwrap())
+                );
+            }
+        }
+        problems.retain(|p| p.fix.is_none());
+    }
+
+    for problem in &problems {
+        println!("{}", problem.colored(&palette));
+    }
+    if !problems.is_empty() {
+        bail!(Failure::Problems(problems.len()));
+    }
+    Ok(())
+}
+
+fn unused(args: &Args, unused_args: &UnusedArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let palette = color::Palette::stdout(args.color);
+    let matcher = load_matcher(args, &st_dir)?;
+    // every file and directory counts, including ignored ones
+    let (unused, unreadable) = matcher.unused(&st_dir);
+    if !unreadable.is_empty() && !args.quiet() {
+        eprintln!(
+            "{} {} path{} couldn't be read, patterns matching only them are reported as unused",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            unreadable.len(),
+            if unreadable.len() > 1 { "s" } else { "" },
+        );
+    }
+    if unused.is_empty() {
+        if !args.quiet() {
+            println!("Every pattern matches something.");
+        }
+        return Ok(());
+    }
+    let dry_run = !args.yes && !is_interactive() && args.non_interactive == NonInteractive::DryRun;
+    if !unused_args.remove || dry_run {
+        let (shown, left) = unused_args.page.page(&unused);
+        let text: String = shown
+            .iter()
+            .map(|rule| {
+                let at = match &rule.location {
+                    Some((file, n)) => format!("{}:{n}:", file.display()),
+                    None => String::new(),
+                };
+                format!("{} {}\n", palette.location(&at), rule.line)
+            })
+            .collect();
+        page(args, &text);
+        unused_args.page.note_left(args, left, shown.len());
+        return Ok(());
+    }
+    if !args.yes && !is_interactive() {
+        bail!(Failure::NotInteractive(
+            "Remove unused patterns?".to_string()
+        ));
+    }
+
+    // line numbers to remove, by ignore file
+    let mut selected: BTreeMap<&Path, Vec<usize>> = BTreeMap::new();
+    for rule in &unused {
+        let Some((file, n)) = &rule.location else {
+            continue;
+        };
+        if args.yes || ask(&format!("Remove {} ({}:{n})?", rule.line, file.display())) {
+            selected.entry(file).or_default().push(*n);
+        }
+    }
+    for (file, lines) in selected {
+        let contents = read_ignore_file(args.backend(), file)?.unwrap_or_default();
+        let kept: String = contents
+            .split_inclusive('\n')
+            .enumerate()
+            .filter(|(n, _)| !lines.contains(&(n + 1)))
+            .map(|(_, line)| line)
+            .collect();
+        enforce_policy(args.backend(), &st_dir, file, &contents, &kept)?;
+        if !args.quiet() {
+            println!(
+                "Removing from {}:",
+                palette.link(&file.display().to_string(), file)
+            );
+            for (n, line) in contents.lines().enumerate() {
+                if lines.contains(&(n + 1)) {
+                    println!("{}", palette.removed(line.trim()));
+                }
+            }
+        }
+        rewrite(args.backend(), file, &kept)?;
+    }
+    Ok(())
+}
+
+fn stats(args: &Args, stats_args: &StatsArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let matcher = load_matcher(args, &st_dir)?;
+    let largest_ignored = stats_args.largest_ignored.unwrap_or(0);
+    let largest_synced = stats_args.largest_synced.unwrap_or(0);
+    let mut totals = stats::Totals::collect(
+        &st_dir,
+        &matcher,
+        &color::Palette::stderr(args.color),
+        largest_ignored.max(largest_synced),
+    );
+    totals.largest_ignored.truncate(largest_ignored);
+    totals.largest_synced.truncate(largest_synced);
+
+    if stats_args.badge {
+        println!("{}", totals.badge());
+    } else if stats_args.largest_ignored.is_some() || stats_args.largest_synced.is_some() {
+        let palette = color::Palette::stdout(args.color);
+        if stats_args.largest_ignored.is_some() {
+            println!("Largest ignored:");
+            for item in &totals.largest_ignored {
+                let rule = matcher
+                    .rule_for(&item.path)
+                    .map(|r| format!("ignored by {}", r.line))
+                    .unwrap_or_else(|| "syncthing's internal file".to_string());
+                println!(
+                    "{:>10}  {}{}  {}",
+                    stats::human_size(item.size),
+                    palette.link(&item.path, &st_dir.join(&item.path)),
+                    if item.dir { "/" } else { "" },
+                    palette.location(&rule)
+                );
+            }
+        }
+        if stats_args.largest_synced.is_some() {
+            println!("Largest synced:");
+            for item in &totals.largest_synced {
+                let pattern = format!("/{}", syntax::escape(&item.path));
+                println!(
+                    "{:>10}  {}  {}",
+                    stats::human_size(item.size),
+                    palette.link(&item.path, &st_dir.join(&item.path)),
+                    palette.location(&format!("stignore -a '{}'", pattern.replace('\'', "'\\''")))
+                );
+            }
+        }
+    } else {
+        println!("{totals}");
+    }
+    Ok(())
+}
+
+fn du(args: &Args) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let matcher = load_matcher(args, &st_dir)?;
+    let totals = stats::Totals::collect(&st_dir, &matcher, &color::Palette::stderr(args.color), 0);
+    let palette = color::Palette::stdout(args.color);
+    let mut dirs: Vec<_> = totals.ignored_by_dir.iter().collect();
+    dirs.sort_by_key(|(_, (_, size))| std::cmp::Reverse(*size));
+    for (dir, (files, size)) in dirs {
+        let name = if dir.is_empty() {
+            "(files in the folder root)".to_string()
+        } else {
+            format!("{}/", palette.link(dir, &st_dir.join(dir)))
+        };
+        println!(
+            "{:>10}  {name}  {}",
+            stats::human_size(*size),
+            palette.location(&format!(
+                "{files} file{}",
+                if *files == 1 { "" } else { "s" }
+            ))
+        );
+    }
+    println!("{totals}");
+    Ok(())
+}
+
+/// Is `dir` the root of a syncthing folder: has a marker or old versions of files
+fn is_folder_root(dir: &Path, markers: &[String]) -> bool {
+    has_marker(dir, markers) || dir.join(".stversions").is_dir()
+}
+
+/// Does `dir` have a syncthing folder inside of it, or is one itself.
+/// Directories that can't be read might, so they do
+fn contains_folder(dir: &Path, markers: &[String]) -> bool {
+    if is_folder_root(dir, markers) {
+        return true;
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return true;
+    };
+    entries
+        .flatten()
+        .any(|e| e.file_type().is_ok_and(|t| t.is_dir()) && contains_folder(&e.path(), markers))
+}
+
+/// Adds the ignored `entry` of the folder `st_dir` to `parts`, split into
+/// files and directories that don't have other syncthing folders inside.
+/// Roots of those folders are added to `nested`
+fn without_nested(
+    st_dir: &Path,
+    entry: matcher::Entry,
+    markers: &[String],
+    parts: &mut Vec<matcher::Entry>,
+    nested: &mut Vec<String>,
+) {
+    let path = st_dir.join(&entry.path);
+    if !entry.dir || !contains_folder(&path, markers) {
+        parts.push(entry);
+        return;
+    }
+    let children = match std::fs::read_dir(&path) {
+        Ok(children) if !is_folder_root(&path, markers) => children,
+        _ => {
+            nested.push(entry.path);
+            return;
+        }
+    };
+    for child in children.flatten() {
+        let Ok(file_type) = child.file_type() else {
+            continue;
+        };
+        let (size, files) = if file_type.is_dir() {
+            matcher::dir_size(&child.path(), &mut Vec::new())
+        } else {
+            (child.metadata().map_or(0, |m| m.len()), 1)
+        };
+        let child = matcher::Entry {
+            path: format!("{}/{}", entry.path, child.file_name().to_string_lossy()),
+            dir: file_type.is_dir(),
+            ignored: true,
+            size,
+            files,
+        };
+        without_nested(st_dir, child, markers, parts, nested);
+    }
+}
+
+fn clean(args: &Args, clean_args: &CleanArgs) -> Result<()> {
+    use question::{Answer, Question};
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let matcher = load_matcher(args, &st_dir)?;
+    let internal = internal_paths(args, &st_dir)?;
+    let palette = color::Palette::stdout(args.color);
+    let markers = markers(args);
+    let mut entries = Vec::new();
+    let mut protected = Vec::new();
+    let mut nested = Vec::new();
+    let mut roots = std::collections::HashMap::new();
+    let unreadable = matcher::walk(&st_dir, &matcher, &mut |entry| {
+        if !entry.ignored {
+            return;
+        }
+        // other syncthing folders inside of this one aren't touched
+        let mut ancestors = entry.path.match_indices('/').map(|(n, _)| &entry.path[..n]);
+        let root = ancestors.find(|dir| {
+            *roots
+                .entry(dir.to_string())
+                .or_insert_with(|| is_folder_root(&st_dir.join(dir), markers))
+        });
+        if let Some(root) = root {
+            if !nested.iter().any(|n| n == root) {
+                nested.push(root.to_string());
+            }
+            return;
+        }
+        // ignore files and markers survive, even inside of ignored directories
+        let inside = |p: &str, dir: &str| p.strip_prefix(dir).is_some_and(|r| r.starts_with('/'));
+        if internal
+            .iter()
+            .any(|p| *p == entry.path || inside(p, &entry.path) || inside(&entry.path, p))
+        {
+            protected.push(entry.path);
+        } else {
+            without_nested(&st_dir, entry, markers, &mut entries, &mut nested);
+        }
+    });
+    if !unreadable.is_empty() && !args.quiet() {
+        eprintln!(
+            "{} {} path{} couldn't be read and {} kept",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            unreadable.len(),
+            if unreadable.len() > 1 { "s" } else { "" },
+            if unreadable.len() > 1 { "are" } else { "is" },
+        );
+    }
+    if !protected.is_empty() && !args.quiet() {
+        eprintln!(
+            "{} keeping {}, syncthing needs {} to sync the folder",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            protected.join(", "),
+            if protected.len() > 1 { "them" } else { "it" },
+        );
+    }
+    if !nested.is_empty() && !args.quiet() {
+        nested.sort();
+        eprintln!(
+            "{} keeping {}, {}",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            nested.join(", "),
+            if nested.len() > 1 {
+                "they are other syncthing folders"
+            } else {
+                "it's another syncthing folder"
+            },
+        );
+    }
+    if entries.is_empty() {
+        if !args.quiet() {
+            println!("No ignored files to delete.");
+        }
+        return Ok(());
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let (files, size) = entries
+        .iter()
+        .fold((0, 0), |(files, size), e| (files + e.files, size + e.size));
+    let summary = format!(
+        "{files} file{}, {}",
+        if files == 1 { "" } else { "s" },
+        stats::human_size(size)
+    );
+    let show = |entry: &matcher::Entry| {
+        format!(
+            "{:>10}  {}{}",
+            stats::human_size(entry.size),
+            palette.link(&entry.path, &st_dir.join(&entry.path)),
+            if entry.dir { "/" } else { "" }
+        )
+    };
+
+    let dry_run = clean_args.delete
+        && !args.yes
+        && !is_interactive()
+        && args.non_interactive == NonInteractive::DryRun;
+    if !clean_args.delete || dry_run {
+        if !args.quiet() {
+            println!("Would delete:");
+            for entry in &entries {
+                println!("{}", show(entry));
+            }
+            if dry_run {
+                println!("{summary}. Not running in a terminal, no changes were made.");
+            } else {
+                println!("{summary}. Pass --delete to delete them.");
+            }
+        }
+        return Ok(());
+    }
+    if !args.yes && !is_interactive() {
+        bail!(Failure::NotInteractive("Delete ignored files?".to_string()));
+    }
+    if !args.yes && !clean_args.interactive {
+        for entry in &entries {
+            println!("{}", show(entry));
+        }
+        if !ask(&format!("Delete {summary}?")) {
+            bail!(Failure::Aborted);
+        }
+    }
+    for entry in &entries {
+        if clean_args.interactive && !args.yes {
+            let answer = Question::new(&format!("Delete {}?", show(entry).trim_start()))
+                .until_acceptable()
+                .default(Answer::NO)
+                .show_defaults()
+                .confirm();
+            if answer != Answer::YES {
+                continue;
+            }
+        }
+        let path = st_dir.join(&entry.path);
+        args.backend()
+            .remove(&path)
+            .with_context(|| format!("Can't delete {}", path.display()))?;
+        if !args.quiet() {
+            println!("Deleted {}", show(entry).trim_start());
+        }
+    }
+    Ok(())
+}
+
+fn bench(args: &Args, bench_args: &BenchArgs) -> Result<()> {
+    let st_dir = if bench_args.real || bench_args.rules.is_none() {
+        Some(find_syncthing_dir(args)?.0)
+    } else {
+        None
+    };
+    let matcher = match (&bench_args.rules, &st_dir) {
+        (Some(rules), _) => {
+            matcher::Matcher::from_file(args.backend(), rules)?.with_markers(markers(args))
+        }
+        (None, Some(st_dir)) => load_matcher(args, st_dir)?,
+        (None, None) => unreachable!("folder is found when --rules is missing"),
+    };
+    let paths = match &st_dir {
+        Some(st_dir) if bench_args.real => {
+            bench::folder_paths(st_dir, bench_args.paths, markers(args))
+        }
+        _ => bench::synthetic_paths(bench_args.paths),
+    };
+
+    let results = bench::run(&matcher, &paths);
+    println!("{results}");
+    let palette = color::Palette::stdout(args.color);
+    for timing in results.slow() {
+        println!(
+            "{} {} ({:.2?} per path): {}",
+            palette.note("Slow:"),
+            timing.pattern,
+            timing.per_path,
+            timing.slow.as_ref().unwrap()
+        );
+    }
+    Ok(())
+}
+
+fn global(args: &Args, global_args: &GlobalArgs) -> Result<()> {
+    match global_args.command {
+        GlobalCommand::Add { ref pattern } => {
+            let patterns = process_patterns(pattern, None, syntax::Modifiers::default())?;
+            global::add(args.backend(), &patterns)?;
+            if !args.quiet() {
+                println!(
+                    "Appending to {}:\n{}",
+                    global::patterns_path()?.display(),
+                    color::Palette::stdout(args.color).patterns(&patterns, None)
+                );
+            }
+        }
+        GlobalCommand::Sync => {
+            let (st_dir, _) = find_syncthing_dir(args)?;
+            global::register(args.backend(), &st_dir)?;
+        }
+    }
+
+    let palette = color::Palette::stderr(args.color);
+    let markers = markers(args);
+    for folder in global::folders(args.backend())? {
+        if !has_marker(&folder, markers) {
+            if !args.quiet() {
+                eprintln!(
+                    "{} {} is no longer a syncthing folder, skipping it",
+                    palette.note("NOTE:"),
+                    folder.display()
+                );
+            }
+            continue;
+        }
+        if global::sync(args.backend(), &folder)? && !args.quiet() {
+            println!("Updated {}", folder.join(global::COPY).display());
+        }
+    }
+    Ok(())
+}
+
+fn list(args: &Args, list_args: &ListArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let files = ignore_files(args.backend(), &st_dir);
+    let palette = color::Palette::stdout(args.color);
+    let link = |path: &Path| palette.link(&path.display().to_string(), path);
+    let page = list_args.page;
+    if !list_args.unused_includes && !list_args.gc {
+        let (shown, left) = page.page(&files);
+        let text: String = shown.iter().map(|f| format!("{}\n", link(f))).collect();
+        self::page(args, &text);
+        page.note_left(args, left, shown.len());
+        return Ok(());
+    }
+
+    let orphans = includes::orphans(&st_dir, &files, markers(args));
+    let dangling = includes::dangling(args.backend(), &files)?;
+    if !list_args.gc {
+        // both lists are paged together, each under its own title
+        let entries: Vec<_> = orphans
+            .iter()
+            .map(|o| ("Not included by any ignore file:", link(o)))
+            .chain(
+                dangling
+                    .iter()
+                    .map(|d| ("Includes of missing files:", d.to_string())),
+            )
+            .collect();
+        let (shown, left) = page.page(&entries);
+        let mut text = String::new();
+        let mut title = None;
+        for (entry_title, entry) in shown {
+            if title != Some(entry_title) {
+                text.push_str(entry_title);
+                text.push('\n');
+                title = Some(entry_title);
+            }
+            text.push_str(&format!("  {entry}\n"));
+        }
+        self::page(args, &text);
+        page.note_left(args, left, shown.len());
+        return Ok(());
+    }
+
+    let removable: Vec<_> = orphans
+        .iter()
+        .filter(|o| includes::is_generated(args.backend(), o))
+        .collect();
+    if removable.is_empty() && dangling.is_empty() {
+        if !args.quiet() {
+            println!("Nothing to clean up");
+        }
+        return Ok(());
+    }
+    if !args.quiet() {
+        for orphan in &removable {
+            println!(
+                "Removing {}",
+                palette.removed(&orphan.display().to_string())
+            );
+        }
+        for d in &dangling {
+            println!(
+                "{} {}",
+                palette.location(&format!("{}:{}:", d.file.display(), d.line)),
+                palette.removed(&d.text)
+            );
+        }
+    }
+    for orphan in &removable {
+        enforce_policy(
+            args.backend(),
+            &st_dir,
+            orphan,
+            &corrupt::read(args.backend(), orphan)?,
+            "",
+        )?;
+    }
+    let fixed: Vec<_> = files
+        .iter()
+        .filter(|file| dangling.iter().any(|d| &d.file == *file))
+        .map(|file| {
+            Ok((
+                file,
+                includes::without_dangling(args.backend(), file, &dangling)?,
+            ))
+        })
+        .collect::<Result<_>>()?;
+    for (file, contents) in &fixed {
+        enforce_policy(
+            args.backend(),
+            &st_dir,
+            file,
+            &corrupt::read(args.backend(), file)?,
+            contents,
+        )?;
+    }
+    confirm(args, "Proceed?")?;
+
+    for orphan in removable {
+        args.backend()
+            .remove(orphan)
+            .with_context(|| format!("Can't remove {}", orphan.display()))?;
+    }
+    for (file, contents) in fixed {
+        rewrite(args.backend(), file, &contents)?;
+    }
+    Ok(())
+}
+
+fn ignore_delete(args: &Args, ignore_delete_args: &IgnoreDeleteArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let config = syncthing::Config::load()?;
+    let folder = shared_folder(&config, &st_dir)?;
+    let name = if folder.label.is_empty() {
+        &folder.id
+    } else {
+        &folder.label
+    };
+    let api = local_api(args, &config)?;
+    let current = api.folder(&folder.id)?["ignoreDelete"] == true;
+
+    let state = match ignore_delete_args.state {
+        None => {
+            println!(
+                "ignoreDelete is {} for folder {name}",
+                if current { "on" } else { "off" }
+            );
+            return Ok(());
+        }
+        Some(state) => state == Toggle::On,
+    };
+    if state == current {
+        if !args.quiet() {
+            println!(
+                "ignoreDelete is already {}",
+                if state { "on" } else { "off" }
+            );
+        }
+        return Ok(());
+    }
+    if !args.quiet() {
+        if state {
+            println!(
+                "With ignoreDelete on, files deleted on other devices won't be deleted \
+                from this one, and this device will be out of sync with the others. \
+                Syncthing documentation discourages it for most setups."
+            );
+        } else {
+            println!("Deletions on other devices will be applied to this one again.");
+        }
+    }
+    confirm(
+        args,
+        &format!(
+            "Turn ignoreDelete {} for folder {name}?",
+            if state { "on" } else { "off" }
+        ),
+    )?;
+    api.patch_folder(&folder.id, serde_json::json!({ "ignoreDelete": state }))
+}
+
+fn remove(args: &Args, remove_args: &RemoveArgs) -> Result<()> {
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    let absolute = args.absolute || remove_args.absolute;
+    let patterns = process_patterns(
+        &remove_args.pattern,
+        if absolute { None } else { Some(&prefix) },
+        syntax::Modifiers::default(),
+    )?;
+    let patterns: Vec<_> = patterns.lines().filter(|p| !p.trim().is_empty()).collect();
+    let path = resolve_target(args, &st_dir, &mut Report::default())?;
+    let contents = read_ignore_file(args.backend(), &path)?.unwrap_or_default();
+
+    let mut removed = Vec::new();
+    let kept: String = contents
+        .split_inclusive('\n')
+        .filter(|line| {
+            let found = patterns.contains(&line.trim());
+            if found {
+                removed.push(line.trim());
+            }
+            !found
+        })
+        .collect();
+    let missing: Vec<_> = patterns
+        .iter()
+        .filter(|p| !removed.contains(p))
+        .map(|p| p.to_string())
+        .collect();
+    if removed.is_empty() {
+        bail!(Failure::NotFound {
+            path,
+            patterns: missing
+        });
+    }
+    enforce_policy(args.backend(), &st_dir, &path, &contents, &kept)?;
+
+    if !args.quiet() {
+        let palette = color::Palette::stdout(args.color);
+        println!(
+            "Removing from {}:",
+            palette.link(&path.display().to_string(), &path)
+        );
+        for line in &removed {
+            println!("{}", palette.removed(line));
+        }
+        if !missing.is_empty() {
+            eprintln!(
+                "{} not found: {}",
+                color::Palette::stderr(args.color).note("NOTE:"),
+                missing.join(", ")
+            );
+        }
+    }
+    rewrite(args.backend(), &path, &kept)
+}
+
+fn migrate(args: &Args, migrate_args: &MigrateArgs) -> Result<()> {
+    if !(migrate_args.to_layered || migrate_args.to_sync || migrate_args.to_local) {
+        bail!("Pass one of --to-layered, --to-sync or --to-local");
+    }
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    let backend = args.backend();
+    let stignore = st_dir.join(".stignore");
+    let stignore_sync = st_dir.join(".stignore_sync");
+    let included = is_stignore_sync_included(backend, &stignore)?;
+    if included && migrate_args.to_layered {
+        if !args.quiet() {
+            println!("{} already includes .stignore_sync", stignore.display());
+        }
+        return Ok(());
+    }
+    let absolute = args.absolute || migrate_args.absolute;
+    // each pattern as it would be added, or as it's written in the file
+    let mut variants = Vec::new();
+    for input in &migrate_args.pattern {
+        let processed = process_patterns(
+            std::slice::from_ref(input),
+            (!absolute).then_some(&prefix),
+            syntax::Modifiers::default(),
+        )?;
+        let mut lines: Vec<_> = processed.lines().map(|l| l.trim().to_string()).collect();
+        lines.push(input.trim().to_string());
+        variants.push(lines);
+    }
+    let selected: Vec<_> = variants.iter().flatten().map(String::as_str).collect();
+    if migrate_args.to_local {
+        return migrate_to_local(args, &st_dir, migrate_args, &variants);
+    }
+    let contents = read_ignore_file(backend, &stignore)?.unwrap_or_default();
+
+    // patterns mentioning this machine are local
+    let names: Vec<_> = [host_name(), user_name()]
+        .into_iter()
+        .flatten()
+        .filter(|n| n.len() > 2)
+        .collect();
+    let ask_user = is_interactive() && !args.yes;
+    let mut layers = Vec::new();
+    for line in layered::patterns(&contents) {
+        let layer = match layered::classify(line, &names) {
+            _ if selected.contains(&line) => layered::Layer::Shared,
+            _ if !selected.is_empty() => layered::Layer::Local,
+            Some(layer) => layer,
+            None if ask_user => ask_layer(line),
+            None => layered::Layer::Local,
+        };
+        layers.push(layer);
+    }
+    if !layers.contains(&layered::Layer::Shared) {
+        if !selected.is_empty() {
+            bail!(Failure::NotFound {
+                path: stignore,
+                patterns: migrate_args.pattern.clone(),
+            });
+        }
+        if !args.quiet() {
+            println!("No patterns to share, nothing to do.");
+        }
+        return Ok(());
+    }
+    warn_about_unmoved(args, &migrate_args.pattern, &variants, &contents);
+
+    let (local, shared) = layered::split(&contents, &layers, !included);
+    let had_sync = read_ignore_file(backend, &stignore_sync)?;
+    let old_sync = had_sync.clone().unwrap_or_default();
+    let mut sync_contents = old_sync.clone();
+    if !sync_contents.is_empty() && !sync_contents.ends_with('\n') {
+        sync_contents.push_str(LINE_ENDING);
+    }
+    sync_contents.push_str(&shared);
+
+    if !args.quiet() {
+        let palette = color::Palette::stdout(args.color);
+        println!("{} (shared):", stignore_sync.display());
+        print!("{}", palette.patterns(&shared, None));
+        println!("{} (local):", stignore.display());
+        print!("{}", palette.patterns(&local, None));
+    }
+    // rules move to the place of the #include, that matters only for negations
+    let first_shared = layers.iter().position(|l| *l == layered::Layer::Shared);
+    let last_shared = layers.iter().rposition(|l| *l == layered::Layer::Shared);
+    let reordered = layers[first_shared.unwrap_or(0)..=last_shared.unwrap_or(0)]
+        .contains(&layered::Layer::Local);
+    if reordered
+        && !args.quiet()
+        && layered::patterns(&contents)
+            .iter()
+            .any(|l| syntax::split_modifiers(l).0.contains('!'))
+    {
+        eprintln!(
+            "{} local patterns between shared ones will be checked after all shared \
+            patterns, make sure that negations still work as intended",
+            color::Palette::stderr(args.color).note("NOTE:")
+        );
+    }
+    enforce_policy(
+        args.backend(),
+        &st_dir,
+        &stignore_sync,
+        &old_sync,
+        &sync_contents,
+    )?;
+    enforce_policy(args.backend(), &st_dir, &stignore, &contents, &local)?;
+    confirm(args, "Proceed?")?;
+
+    // .stignore_sync first: if .stignore can't be written, nothing is lost
+    rewrite_both(
+        backend,
+        (&stignore_sync, had_sync.as_deref(), &sync_contents),
+        (&stignore, &local),
+    )
+}
+
+/// Rewrites `first` and then `second` file with their new contents. If
+/// `second` can't be written, `first` gets its `old` contents back (or is
+/// removed if it didn't exist), so either both files change or neither does
+fn rewrite_both(
+    backend: &dyn Backend,
+    (first, old, new): (&Path, Option<&str>, &str),
+    (second, second_new): (&Path, &str),
+) -> Result<()> {
+    rewrite(backend, first, new)?;
+    let Err(err) = rewrite(backend, second, second_new) else {
+        return Ok(());
+    };
+    let restored = match old {
+        Some(old) => rewrite(backend, first, old),
+        None => backend.remove(first).map_err(Into::into),
+    };
+    match restored {
+        Ok(()) => info!("Restored {}", first.display()),
+        Err(e) => {
+            return Err(err.context(format!(
+                "{} was already changed and can't be restored: {e}",
+                first.display()
+            )))
+        }
+    }
+    Err(err)
+}
+
+/// Notes `inputs` that none of the `variants` of are in `contents` of the source file
+fn warn_about_unmoved(args: &Args, inputs: &[String], variants: &[Vec<String>], contents: &str) {
+    let present = layered::patterns(contents);
+    let missing: Vec<_> = inputs
+        .iter()
+        .zip(variants)
+        .filter(|(_, v)| !v.iter().any(|v| present.contains(&v.as_str())))
+        .map(|(input, _)| input.as_str())
+        .collect();
+    if !missing.is_empty() && !args.quiet() {
+        eprintln!(
+            "{} not found: {}",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            missing.join(", ")
+        );
+    }
+}
+
+/// Moves `selected` patterns (all if empty) of `.stignore_sync` to `.stignore`,
+/// above `#include .stignore_sync`, so they are checked at the same point
+fn migrate_to_local(
+    args: &Args,
+    st_dir: &Path,
+    migrate_args: &MigrateArgs,
+    variants: &[Vec<String>],
+) -> Result<()> {
+    let selected: Vec<_> = variants.iter().flatten().map(String::as_str).collect();
+    let backend = args.backend();
+    let stignore = st_dir.join(".stignore");
+    let stignore_sync = st_dir.join(".stignore_sync");
+    let sync_contents = read_ignore_file(backend, &stignore_sync)?.unwrap_or_default();
+    let layers: Vec<_> = layered::patterns(&sync_contents)
+        .iter()
+        .map(|line| {
+            if selected.is_empty() || selected.contains(line) {
+                layered::Layer::Local
+            } else {
+                layered::Layer::Shared
+            }
+        })
+        .collect();
+    if !layers.contains(&layered::Layer::Local) {
+        if selected.is_empty() {
+            if !args.quiet() {
+                println!(
+                    "{} has no patterns, nothing to do.",
+                    stignore_sync.display()
+                );
+            }
+            return Ok(());
+        }
+        bail!(Failure::NotFound {
+            path: stignore_sync,
+            patterns: migrate_args.pattern.clone(),
+        });
+    }
+    warn_about_unmoved(args, &migrate_args.pattern, variants, &sync_contents);
+
+    let (moved, shared) = layered::split(&sync_contents, &layers, false);
+    let had_stignore = read_ignore_file(backend, &stignore)?;
+    let contents = had_stignore.clone().unwrap_or_default();
+    let re = Regex::new(r"^\s*#include\s+\.stignore_sync\s*$").unwrap();
+    let local = match contents.lines().position(|l| re.is_match(l)) {
+        Some(n) => insert_at(&contents, n, &moved),
+        None => {
+            let mut local = contents.clone();
+            if !local.is_empty() && !local.ends_with('\n') {
+                local.push_str(LINE_ENDING);
+            }
+            local.push_str(&moved);
+            local
+        }
+    };
+
+    if !args.quiet() {
+        let palette = color::Palette::stdout(args.color);
+        println!("Moving to {}:", stignore.display());
+        print!("{}", palette.patterns(&moved, None));
+    }
+    enforce_policy(args.backend(), st_dir, &stignore, &contents, &local)?;
+    enforce_policy(
+        args.backend(),
+        st_dir,
+        &stignore_sync,
+        &sync_contents,
+        &shared,
+    )?;
+    confirm(args, "Proceed?")?;
+
+    // .stignore first: if .stignore_sync can't be written, nothing is lost
+    rewrite_both(
+        backend,
+        (&stignore, had_stignore.as_deref(), &local),
+        (&stignore_sync, &shared),
+    )
+}
+
+/// Asks whether pattern `line` should be shared with other devices
+fn ask_layer(line: &str) -> layered::Layer {
+    use question::{Answer, Question};
+    let answer = Question::new(&format!(
+        "{line}: share with other devices? (s - shared/L - local)"
+    ))
+    .acceptable(vec!["s", "shared", "l", "local"])
+    .until_acceptable()
+    .default(Answer::RESPONSE("l".to_string()))
+    .ask();
+    match answer {
+        Some(Answer::RESPONSE(r)) if r.starts_with('s') => layered::Layer::Shared,
+        _ => layered::Layer::Local,
+    }
+}
+
+/// Rewrites the `.stignore.d` section of `.stignore`, so it includes every fragment.
+/// Fragments included elsewhere are left alone
+fn assemble(args: &Args) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let stignore = st_dir.join(".stignore");
+    let contents = read_ignore_file(args.backend(), &stignore)?.unwrap_or_default();
+    let generated = section::lines(&contents, fragments::SECTION);
+    let elsewhere: Vec<_> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !generated.iter().any(|g| g.trim() == *l))
+        .collect();
+    let includes: Vec<_> = fragments::list(&st_dir)?
+        .iter()
+        .map(|f| fragments::include(f))
+        .filter(|i| !elsewhere.contains(&i.as_str()))
+        .collect();
+    let includes: Vec<_> = includes.iter().map(String::as_str).collect();
+    let current: Vec<_> = generated
+        .iter()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    if current == includes {
+        if !args.quiet() {
+            println!("{} already includes every fragment", stignore.display());
+        }
+        return Ok(());
+    }
+    let new = section::replace(&contents, fragments::SECTION, &includes);
+    enforce_policy(args.backend(), &st_dir, &stignore, &contents, &new)?;
+    if !args.quiet() {
+        let palette = color::Palette::stdout(args.color);
+        println!(
+            "Section {} of {}:",
+            section::header(fragments::SECTION),
+            palette.link(&stignore.display().to_string(), &stignore)
+        );
+        for line in &current {
+            if !includes.contains(line) {
+                println!("{}", palette.removed(&format!("- {line}")));
+            }
+        }
+        for line in &includes {
+            if !current.contains(line) {
+                println!("{}", palette.added(&format!("+ {line}")));
+            }
+        }
+    }
+    rewrite(args.backend(), &stignore, &new)
+}
+
+fn tidy(args: &Args, tidy_args: &TidyArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let path = resolve_target(args, &st_dir, &mut Report::default())?;
+    let contents = read_ignore_file(args.backend(), &path)?.unwrap_or_default();
+    let tidied = tidy::tidy(&contents, tidy_args.sort);
+    if tidied.contents == contents {
+        if !args.quiet() {
+            println!("{} is already tidy", path.display());
+        }
+        return Ok(());
+    }
+
+    if !args.quiet() {
+        let palette = color::Palette::stdout(args.color);
+        println!(
+            "{} {}:",
+            if tidy_args.check {
+                "Would tidy"
+            } else {
+                "Tidying"
+            },
+            palette.link(&path.display().to_string(), &path)
+        );
+        for duplicate in &tidied.duplicates {
+            println!("  duplicate {}", palette.removed(duplicate));
+        }
+        if tidied.sorted > 0 {
+            println!(
+                "  sorted {} group{}",
+                tidied.sorted,
+                if tidied.sorted > 1 { "s" } else { "" }
+            );
+        }
+        if tidied.blank_lines > 0 {
+            println!(
+                "  removed {} blank line{}",
+                tidied.blank_lines,
+                if tidied.blank_lines > 1 { "s" } else { "" }
+            );
+        }
+        if tidied.unsortable > 0 {
+            eprintln!(
+                "{} {} group{} with negations or (?d) left unsorted, their order matters",
+                color::Palette::stderr(args.color).note("NOTE:"),
+                tidied.unsortable,
+                if tidied.unsortable > 1 { "s" } else { "" }
+            );
+        }
+    }
+    if tidy_args.check {
+        bail!(Failure::InvalidFile {
+            path,
+            problems: tidied.duplicates.len() + tidied.sorted + usize::from(tidied.blank_lines > 0),
+        });
+    }
+    enforce_policy(args.backend(), &st_dir, &path, &contents, &tidied.contents)?;
+    rewrite(args.backend(), &path, &tidied.contents)
+}
+
+/// `input` (relative to the current directory) as a `/`-separated path
+/// relative to the folder `st_dir`. The path doesn't have to exist
+fn folder_relative(args: &Args, st_dir: &Path, input: &str) -> Result<String> {
+    let path = normalize(&current_dir(args)?.join(input));
+    let Ok(relative) = path.strip_prefix(st_dir) else {
+        bail!(
+            "{input} is outside of syncthing folder {}",
+            st_dir.display()
+        );
+    };
+    if relative.as_os_str().is_empty() {
+        bail!("{input} is the root of syncthing folder");
+    }
+    Ok(relative
+        .components()
+        .map(|c| winpath::utf8(c.as_os_str()))
+        .collect::<Result<Vec<_>>>()?
+        .join("/"))
+}
+
+fn test(args: &Args, test_args: &TestArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let matcher = load_matcher(args, &st_dir)?;
+    let palette = color::Palette::stdout(args.color);
+    for input in &test_args.path {
+        let path = folder_relative(args, &st_dir, input)?;
+        if matcher.is_internal(&path) {
+            println!(
+                "{input}: {}, it's syncthing's internal file",
+                palette.removed("ignored")
+            );
+            continue;
+        }
+        let Some(rule) = matcher.rule_for(&path) else {
+            println!(
+                "{input}: {}, no pattern matches it",
+                palette.added("synced")
+            );
+            continue;
+        };
+        let location = rule
+            .location
+            .as_ref()
+            .map(|(file, line)| {
+                format!(
+                    " ({})",
+                    palette.link(&format!("{}:{line}", file.display()), file)
+                )
+            })
+            .unwrap_or_default();
+        if rule.negated {
+            println!(
+                "{input}: {}, un-ignored by {}{location}",
+                palette.added("synced"),
+                palette.location(&rule.line)
+            );
+        } else {
+            println!(
+                "{input}: {} by {}{location}",
+                palette.removed("ignored"),
+                palette.location(&rule.line)
+            );
+        }
+        if test_args.explain_regex {
+            for line in rule.explain() {
+                println!("    {line}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints where the pattern is and the --provenance comment of the block it
+/// was appended in, along with other comments of the block (--comment)
+fn blame(args: &Args, blame_args: &BlameArgs) -> Result<()> {
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    let absolute = args.absolute || blame_args.absolute;
+    let processed = process_patterns(
+        std::slice::from_ref(&blame_args.pattern),
+        (!absolute).then_some(&prefix),
+        syntax::Modifiers::default(),
+    )?;
+    let wanted: Vec<_> = processed
+        .lines()
+        .chain([blame_args.pattern.as_str()])
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+
+    let palette = color::Palette::stdout(args.color);
+    let mut found = false;
+    for file in ignore_files(args.backend(), &st_dir) {
+        let Some(contents) = read_ignore_file(args.backend(), &file)? else {
+            continue;
+        };
+        let lines: Vec<_> = contents.lines().map(str::trim).collect();
+        for (n, line) in lines.iter().enumerate() {
+            if !wanted.contains(line) {
+                continue;
+            }
+            found = true;
+            let location = format!("{}:{}", file.display(), n + 1);
+            println!("{}: {line}", palette.link(&location, &file));
+
+            // the block goes up to the nearest blank line
+            let block = lines[..n]
+                .iter()
+                .rposition(|l| l.is_empty())
+                .map_or(0, |b| b + 1);
+            let added = lines[block..n]
+                .iter()
+                .rposition(|l| l.starts_with(PROVENANCE))
+                .map(|a| block + a);
+            let Some(added) = added else {
+                println!("    no provenance comment, add patterns with --provenance to record it");
+                continue;
+            };
+            println!("    added {}", &lines[added][PROVENANCE.len()..]);
+            for comment in lines[added + 1..n]
+                .iter()
+                .take_while(|l| l.starts_with("//"))
+            {
+                println!("    {}", palette.location(comment));
+            }
+        }
+    }
+    if !found {
+        bail!(Failure::NotFound {
+            path: st_dir.join(".stignore"),
+            patterns: vec![blame_args.pattern.clone()],
+        });
+    }
+    Ok(())
+}
+
+/// Prints patterns of the target file that are inside of the current
+/// directory, the way they can be passed to `remove`
+fn complete(args: &Args, complete_args: &CompleteArgs) -> Result<()> {
+    if complete_args.command != "remove" {
+        return Ok(());
+    }
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    let tgt_file = resolve_target(args, &st_dir, &mut Report::default())?;
+    let contents = read_ignore_file(args.backend(), &tgt_file)?.unwrap_or_default();
+    let prefix = prefix.display().to_string();
+    let prefix = prefix.trim_end_matches('/');
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") || line.starts_with("#include") {
+            continue;
+        }
+        let (modifiers, pattern) = syntax::split_modifiers(line);
+        let candidate = if args.absolute {
+            line.to_string()
+        } else {
+            match pattern
+                .strip_prefix(prefix)
+                .and_then(|p| p.strip_prefix('/'))
+            {
+                Some(relative) if !relative.is_empty() => format!("{modifiers}{relative}"),
+                _ => continue,
+            }
+        };
+        if candidate.starts_with(&complete_args.current) {
+            println!("{candidate}");
+        }
+    }
+    Ok(())
+}
+
+fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+/// Asks `question`, failing unless user agrees. Always agrees with --yes
+fn confirm(args: &Args, question: &str) -> Result<()> {
+    if args.yes {
+        return Ok(());
+    }
+    if !is_interactive() {
+        bail!(Failure::NotInteractive(question.to_string()));
+    }
+    if !ask(question) {
+        bail!(Failure::Aborted);
+    }
+    Ok(())
+}
+
+fn ask(question: &str) -> bool {
+    use question::{Answer, Question};
+    Question::new(question)
+        .until_acceptable()
+        .default(Answer::YES)
+        .show_defaults()
+        .confirm()
+        == Answer::YES
+}
+
+fn go(args: &Args, report: &mut Report) -> Result<()> {
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    report.folder = Some(st_dir.clone());
+
+    if let Some(name) = &args.section {
+        if section::name(&section::header(name)) != Some(name.as_str()) {
+            bail!("Invalid section name {name:?}");
+        }
+    }
+    let mut inputs = pattern_inputs(args)?;
+    if !args.paths && !args.literal && !args.fast_append {
+        collapse_expanded_globs(args, &mut inputs, report)?;
+    }
+    let as_is = args.absolute || args.paths;
+    let modifiers = syntax::Modifiers {
+        deletable: args.deletable,
+        case_insensitive: args.case_insensitive
+            || (args.auto_case_insensitive
+                && !args.fast_append
+                && is_case_insensitive_fs(args, &st_dir)),
+        negated: args.negate,
+    };
+    let mut patterns = if args.paths {
+        process_patterns(&path_patterns(args, &st_dir, &inputs)?, None, modifiers)?
+    } else if args.literal {
+        let literal: Vec<_> = inputs
+            .iter()
+            .flat_map(|p| p.lines())
+            .map(|p| syntax::literal(p.trim()))
+            .collect();
+        process_patterns(&literal, (!as_is).then_some(&prefix), modifiers)?
+    } else {
+        process_patterns(&inputs, (!as_is).then_some(&prefix), modifiers)?
+    };
+    patterns = normalize_unicode(args.unicode_form, &patterns);
+    if let Some(idiom) = args.dir {
+        patterns = dir_idiom(args.backend(), &st_dir, &patterns, idiom);
+    }
+    if !args.no_check && !args.fast_append {
+        warn_about_missing(args, &st_dir, &patterns, report);
+    }
+    let mut annotation: Vec<String> = Vec::new();
+    if args.provenance {
+        annotation.push(provenance());
+    }
+    if let Some(comment) = &args.comment {
+        annotation.extend(comment.lines().map(|l| format!("// {}", l.trim())));
+    }
+    if !annotation.is_empty() {
+        let mut annotated: String = annotation
+            .iter()
+            .map(|l| format!("{l}{LINE_ENDING}"))
+            .collect();
+        annotated.push_str(&patterns);
+        patterns = annotated;
+    }
+    report.patterns = patterns.lines().map(String::from).collect();
+    warn_about_ignore_delete(args, &inputs, report);
+    confirm_catch_all(args, &patterns)?;
+    let palette = color::Palette::stdout(args.color);
+    let prefix = (!as_is).then(|| prefix.display().to_string());
+    let colored = |patterns: &str| palette.patterns(patterns, prefix.as_deref());
+    let link = |path: &Path| palette.link(&path.display().to_string(), path);
+
+    if args.fast_append {
+        let tgt_files = match (&args.file, args.target) {
+            (Some(file), _) => vec![st_dir.join(file)],
+            (None, Target::Auto | Target::Stignore) => vec![st_dir.join(".stignore")],
+            (None, Target::StignoreSync) => vec![st_dir.join(".stignore_sync")],
+            (None, Target::Both) => vec![st_dir.join(".stignore"), st_dir.join(".stignore_sync")],
+        };
+        // the policy can't be checked without reading the ignore files
+        if st_dir.join(policy::FILE).exists() {
+            bail!(
+                "{} has a policy, which --fast-append can't check. Add the patterns without it",
+                st_dir.display()
+            );
+        }
+        refuse_internal(args, &st_dir, &patterns)?;
+        report.target = tgt_files.first().cloned();
+        report.other_targets = tgt_files[1..].to_vec();
+        for tgt_file in tgt_files {
+            if !args.quiet() {
+                println!("Appending to {}:\n{}", link(&tgt_file), colored(&patterns));
+            }
+            args.backend()
+                .append(&tgt_file, patterns.as_bytes())
+                .context("Can't append to file")?;
+        }
+        return Ok(());
+    }
+    refuse_internal(args, &st_dir, &patterns)?;
+
+    if args.emit_commands {
+        if args.target == Target::Both && args.file.is_none() {
+            for name in [".stignore", ".stignore_sync"] {
+                print!(
+                    "{}",
+                    emit::append_script(&st_dir, &st_dir.join(name), &patterns)
+                );
+            }
+        } else {
+            let tgt_file = resolve_target(args, &st_dir, report)?;
+            print!("{}", emit::append_script(&st_dir, &tgt_file, &patterns));
+        }
+        return Ok(());
+    }
+
+    quarantine_corrupt(args, &st_dir, report)?;
+    let stignore = st_dir.join(".stignore");
+    let stignore_sync = st_dir.join(".stignore_sync");
+    let fragment = args.fragment.as_ref().and(args.file.as_ref());
+    let wire_fragment =
+        fragment.filter(|f| !is_reachable(args.backend(), &st_dir, &st_dir.join(f)));
+    let targets = if let Some(fragment) = wire_fragment {
+        let path = st_dir.join(fragment);
+        read_ignore_file(args.backend(), &path)?;
+        vec![(path, patterns.clone())]
+    } else if args.target == Target::Both && args.file.is_none() {
+        read_ignore_file(args.backend(), &stignore)?;
+        read_ignore_file(args.backend(), &stignore_sync)?;
+        vec![
+            (stignore.clone(), patterns.clone()),
+            (stignore_sync.clone(), patterns.clone()),
+        ]
+    } else if args.file.is_none() && !args.routes.is_empty() {
+        route_patterns(args, &st_dir, &patterns, report)?
+    } else {
+        vec![(resolve_target(args, &st_dir, report)?, patterns.clone())]
+    };
+    report.target = targets.first().map(|(t, _)| t.clone());
+    report.other_targets = targets[1..].iter().map(|(t, _)| t.clone()).collect();
+    // with both targets the pattern in one of them is there on purpose
+    let check_redundant = !args.allow_redundant && args.target != Target::Both;
+
+    let mut plans = Vec::new();
+    for (tgt_file, mut patterns) in targets {
+        if !args.allow_duplicates {
+            let mut existing = read_ignore_file(args.backend(), &tgt_file)?.unwrap_or_default();
+            if args.check_includes {
+                for file in ignore_files(args.backend(), &st_dir) {
+                    existing.push_str(&corrupt::read(args.backend(), &file)?);
+                    existing.push('\n');
+                }
+            }
+            let skipped = skip_present(&mut patterns, &existing);
+            if !skipped.is_empty() {
+                if !args.quiet() {
+                    eprintln!(
+                        "{} skipping pattern{} already present in {}: {}",
+                        color::Palette::stderr(args.color).note("NOTE:"),
+                        if skipped.len() > 1 { "s" } else { "" },
+                        if args.check_includes {
+                            "ignore files".to_string()
+                        } else {
+                            tgt_file.display().to_string()
+                        },
+                        skipped.join(", ")
+                    );
+                }
+                report.skipped.extend(skipped);
+            }
+        }
+        if check_redundant {
+            skip_redundant(args, &st_dir, &mut patterns, report);
+        }
+        warn_about_shadowed(args, &st_dir, &tgt_file, &patterns, report);
+        if is_only_comments(&patterns) && !report.skipped.is_empty() {
+            continue;
+        }
+        plans.push(Plan {
+            file: tgt_file,
+            old: String::new(),
+            patterns,
+            insertion: None,
+        });
+    }
+    if plans.is_empty() {
+        if !args.quiet() {
+            println!("All patterns are already ignored, nothing to do.");
+        }
+        return Ok(());
+    }
+    report.patterns = plans[0].patterns.lines().map(String::from).collect();
+    if args.stdout {
+        for plan in &plans {
+            if plans.len() > 1 {
+                println!("// {}", plan.file.display());
+            }
+            print!("{}", plan.patterns);
+        }
+        return Ok(());
+    }
+    if is_read_only_mount(&st_dir) {
+        if !args.quiet() {
+            for plan in &plans {
+                print_read_only_fallback(args, &plan.file, &plan.patterns);
+            }
+        }
+        bail!(Failure::ReadOnly(st_dir));
+    }
+    let rewrites = args.section.is_some() || args.sorted || args.prepend || args.before_includes;
+    for plan in &plans {
+        if let Some(problem) = write_problem(&plan.file, rewrites) {
+            bail!(Failure::NotWritable {
+                path: plan.file.clone(),
+                problem
+            });
+        }
+    }
+    let sync_target = matches!(args.target, Target::StignoreSync | Target::Both)
+        && plans.iter().any(|p| p.file == stignore_sync);
+    let wire_target = sync_target && !is_stignore_sync_included(args.backend(), &stignore)?;
+    let target_missing = wire_target || !args.backend().exists(&stignore_sync);
+    let mut include = args.ignore_target_missing == Some(TargetMissing::Create);
+    if wire_target && args.ignore_target_missing.is_none() {
+        include = args.ensure_include
+            || (is_interactive()
+                && !args.yes
+                && !args.quiet()
+                && ask(&format!(
+                    ".stignore_sync isn't included in {}, so its patterns have no effect. \
+                    Add #include .stignore_sync?",
+                    stignore.display()
+                )));
+    }
+    if sync_target && target_missing {
+        let note = match args.ignore_target_missing {
+            Some(TargetMissing::Create) => None,
+            Some(TargetMissing::Skip) => Some(
+                ".stignore_sync isn't set up in this folder yet, no changes were made".to_string(),
+            ),
+            None if wire_target && !include => Some(format!(
+                ".stignore_sync isn't included in {}, its patterns have no effect. \
+                Pass --ensure-include to include it",
+                stignore.display()
+            )),
+            None => None,
+        };
+        if let Some(note) = note {
+            if !args.quiet() {
+                eprintln!(
+                    "{} {note}",
+                    color::Palette::stderr(args.color).note("NOTE:")
+                );
+            }
+            report.warnings.push(note);
+        }
+        if args.ignore_target_missing == Some(TargetMissing::Skip) {
+            report.skipped.append(&mut report.patterns);
+            return Ok(());
+        }
+    }
+    let wire_target = wire_target && include;
+    for plan in &mut plans {
+        plan.old = read_ignore_file(args.backend(), &plan.file)?.unwrap_or_default();
+        plan.insertion = (args.prepend || args.before_includes)
+            .then(|| insertion_line(&plan.old, args.before_includes))
+            .flatten();
+        let (new, _) = planned_contents(args, plan);
+        enforce_policy(args.backend(), &st_dir, &plan.file, &plan.old, &new)?;
+    }
+    let present = if args.show_matches || args.preview {
+        present_files(args, &st_dir)?
+    } else {
+        Vec::new()
+    };
+    let describe = |plans: &[Plan]| -> String {
+        plans
+            .iter()
+            .map(|plan| {
+                let action = match plan.insertion {
+                    Some(0) => "Prepending to",
+                    Some(_) => "Inserting before #includes of",
+                    None => "Appending to",
+                };
+                let matches = if args.show_matches || args.preview {
+                    let matches = describe_matches(&present, &plan.patterns, args.show_matches);
+                    format!("\n{matches}")
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{action} {}:\n{}{matches}",
+                    link(&plan.file),
+                    colored(&plan.patterns)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    if args.preview {
+        page(args, &format!("{}\n", describe(&plans)));
+    } else if !args.quiet() {
+        println!("{}", describe(&plans));
+    }
+    if args.preview
+        && !args.yes
+        && !is_interactive()
+        && args.non_interactive == NonInteractive::DryRun
+    {
+        if !args.quiet() {
+            println!("Not running in a terminal, no changes were made.");
+        }
+        return Ok(());
+    }
+    if args.preview && !args.yes {
+        if !is_interactive() {
+            bail!(Failure::NotInteractive("Proceed?".to_string()));
+        }
+        // edited patterns are added to every target
+        while let Some(edited) = preview_prompt(&plans[0].patterns)? {
+            // edited patterns are already relative to the folder root
+            let edited = process_patterns(&[edited], None, syntax::Modifiers::default())?;
+            confirm_catch_all(args, &edited)?;
+            refuse_internal(args, &st_dir, &edited)?;
+            for plan in &mut plans {
+                plan.patterns = edited.clone();
+            }
+            report.patterns = edited.lines().map(String::from).collect();
+            page(args, &format!("{}\n", describe(&plans)));
+        }
+        for plan in &plans {
+            let (new, _) = planned_contents(args, plan);
+            enforce_policy(args.backend(), &st_dir, &plan.file, &plan.old, &new)?;
+        }
+    }
+    if let Some(dir) = wire_fragment.and_then(|f| st_dir.join(f).parent().map(Path::to_path_buf)) {
+        args.backend()
+            .create_dir_all(&dir)
+            .with_context(|| format!("Can't create {}", dir.display()))?;
+    }
+    if wire_target {
+        let contents = read_ignore_file(args.backend(), &stignore)?.unwrap_or_default();
+        let new = format!("{contents}\n#include .stignore_sync\n");
+        enforce_policy(args.backend(), &st_dir, &stignore, &contents, &new)?;
+    }
+    for plan in &plans {
+        write_plan(args, plan)?;
+    }
+    if let Some(fragment) = wire_fragment {
+        let contents = read_ignore_file(args.backend(), &stignore)?.unwrap_or_default();
+        let include = fragments::include(fragment);
+        let new = section::insert(&contents, fragments::SECTION, &[&include], false);
+        enforce_policy(args.backend(), &st_dir, &stignore, &contents, &new)?;
+        rewrite(args.backend(), &stignore, &new)?;
+        if !args.quiet() {
+            println!(
+                "Included {} into {}",
+                fragment.display(),
+                stignore.display()
+            );
+        }
+    }
+    if wire_target {
+        info!("Including .stignore_sync into {}", stignore.display());
+        append(
+            args.backend(),
+            &stignore,
+            &format!("#include .stignore_sync{LINE_ENDING}"),
+        )
+        .context("Can't append to file")?;
+        if !args.quiet() {
+            println!("Included .stignore_sync into {}", stignore.display());
+        }
+    }
+    if args.retry_rescan {
+        rescan(args, &st_dir)?;
+    }
+    Ok(())
+}
+
+/// Patterns that `go` adds to one of the target files
+struct Plan {
+    file: PathBuf,
+    /// Contents of the file before the change
+    old: String,
+    patterns: String,
+    /// Line that --prepend or --before-includes insert patterns before
+    insertion: Option<usize>,
+}
+
+/// Are there no patterns in `patterns`, only comments added by --comment and --provenance
+fn is_only_comments(patterns: &str) -> bool {
+    patterns
+        .lines()
+        .all(|l| l.trim().is_empty() || l.trim().starts_with("//"))
+}
+
+/// Contents of the file of `plan` with its patterns added at the position
+/// selected by the options, and whether the file has to be rewritten to get
+/// them rather than only appended to
+fn planned_contents(args: &Args, plan: &Plan) -> (String, bool) {
+    let new: Vec<_> = plan
+        .patterns
+        .lines()
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+    let inserted = match (&args.section, plan.insertion) {
+        (Some(name), _) => Some(section::insert(&plan.old, name, &new, args.sorted)),
+        (None, Some(at)) => Some(insert_at(&plan.old, at, &plan.patterns)),
+        (None, None) => args
+            .sorted
+            .then(|| tidy::insert_sorted(&plan.old, &new))
+            .flatten(),
+    };
+    match inserted {
+        Some(contents) => (contents, true),
+        None if plan.old.is_empty() || plan.old.ends_with('\n') => {
+            (format!("{}{}", plan.old, plan.patterns), false)
+        }
+        None => (format!("{}\n{}", plan.old, plan.patterns), false),
+    }
+}
+
+/// Adds patterns of `plan` to its file, at the position selected by the options
+fn write_plan(args: &Args, plan: &Plan) -> Result<()> {
+    match planned_contents(args, plan) {
+        (contents, true) => rewrite(args.backend(), &plan.file, &contents),
+        (_, false) => {
+            if args.sorted {
+                info!("Can't keep {} sorted, appending", plan.file.display());
+            }
+            append(args.backend(), &plan.file, &plan.patterns).context("Can't append to file")
+        }
+    }
+}
+
+/// REST API of the local syncthing: the one from config files, or the GUI from
+/// syncthing's `config`
+fn local_api(args: &Args, config: &syncthing::Config) -> Result<syncthing::Api> {
+    match &args.api {
+        Some(api) => syncthing::Api::remote(&api.url, &api.api_key),
+        None => config.api().cloned(),
+    }
+}
+
+/// Root of the folder `name`: a path, or a label or ID of a folder from syncthing's config.xml
+fn folder_by_name(name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path.is_dir() {
+        return winpath::canonicalize(path).with_context(|| format!("Can't find directory {name}"));
+    }
+    let config = syncthing::Config::load()?;
+    config
+        .folders
+        .iter()
+        .find(|f| f.label == name || f.id == name)
+        .map(|f| f.path.clone())
+        .with_context(|| {
+            format!(
+                "{name} is neither a directory nor a folder from {}",
+                config.path.display()
+            )
+        })
+}
+
+/// Prints patterns that only one of `ours` and `theirs` has, in their order.
+/// Returns whether there were any
+fn print_pattern_diff(
+    palette: &color::Palette,
+    (our_name, ours): (&str, &[String]),
+    (their_name, theirs): (&str, &[String]),
+) -> bool {
+    let only_ours: Vec<_> = ours.iter().filter(|p| !theirs.contains(p)).collect();
+    let only_theirs: Vec<_> = theirs.iter().filter(|p| !ours.contains(p)).collect();
+    if only_ours.is_empty() && only_theirs.is_empty() {
+        if ours != theirs {
+            println!("{our_name} and {their_name} have the same patterns in a different order");
+        }
+        return ours != theirs;
+    }
+    println!("{}", palette.removed(&format!("--- {our_name}")));
+    println!("{}", palette.added(&format!("+++ {their_name}")));
+    for pattern in only_ours {
+        println!("{}", palette.removed(&format!("-{pattern}")));
+    }
+    for pattern in only_theirs {
+        println!("{}", palette.added(&format!("+{pattern}")));
+    }
+    true
+}
+
+/// Compares patterns that syncthing on this device has loaded for the folder
+/// with ones of other devices, or with .stignore on disk if there are no remotes
+fn diff(args: &Args, diff_args: &DiffArgs) -> Result<()> {
+    let (st_dir, _) = find_syncthing_dir(args)?;
+    let config = syncthing::Config::load()?;
+    let folder = shared_folder(&config, &st_dir)?;
+    let api = local_api(args, &config)?;
+    let local = api.ignores(&folder.id)?;
+    let palette = color::Palette::stdout(args.color);
+
+    if diff_args.remotes.is_empty() && args.remotes.is_empty() {
+        let path = st_dir.join(".stignore");
+        let contents = corrupt::read(args.backend(), &path)?;
+        let loaded: Vec<String> = local
+            .ignore
+            .iter()
+            .flatten()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect();
+        let on_disk: Vec<String> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(String::from)
+            .collect();
+        let loaded_name = format!("loaded by syncthing at {}", api.url());
+        let changed = print_pattern_diff(
+            &palette,
+            (&loaded_name, &loaded),
+            (&path.display().to_string(), &on_disk),
+        );
+        if !changed && !args.quiet() {
+            println!("Syncthing uses the current .stignore. Add [remotes.NAME] to config.toml to compare with other devices");
+        }
+        return Ok(());
+    }
+
+    let names: Vec<&String> = if diff_args.remotes.is_empty() {
+        args.remotes.keys().collect()
+    } else {
+        diff_args.remotes.iter().collect()
+    };
+    let ours = local.expanded.unwrap_or_default();
+    for name in names {
+        let remote = args.remotes.get(name).with_context(|| {
+            format!("Remote {name:?} isn't defined in config files, add [remotes.{name}] with url and api-key")
+        })?;
+        let api = syncthing::Api::remote(&remote.url, &remote.api_key)?;
+        let theirs = api
+            .ignores(&folder.id)
+            .with_context(|| format!("Can't get patterns of folder {} from {name}", folder.id))?
+            .expanded
+            .unwrap_or_default();
+        let their_name = format!("{name} ({})", api.url());
+        if !print_pattern_diff(&palette, ("this device", &ours), (&their_name, &theirs))
+            && !args.quiet()
+        {
+            println!("{name} uses the same patterns");
+        }
+    }
+    Ok(())
+}
+
+/// Adds patterns of .stignore of another folder and the files it includes
+/// to the current folder, as-is. #includes themselves aren't copied, since the
+/// included files are in the other folder
+fn copy_from(args: &Args, copy_args: &CopyFromArgs) -> Result<()> {
+    let source = folder_by_name(&copy_args.folder)?;
+    let filter = match &copy_args.filter {
+        Some(glob) => {
+            Some(matcher::Rule::parse(glob).with_context(|| format!("Invalid filter {glob:?}"))?)
+        }
+        None => None,
+    };
+    let mut patterns = Vec::new();
+    for file in ignore_files(args.backend(), &source) {
+        let contents = corrupt::read(args.backend(), &file)?;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with("//") || line.starts_with("#include") {
+                continue;
+            }
+            let (_, pattern) = syntax::split_modifiers(line);
+            let path = pattern.trim_start_matches('/').trim_end_matches('/');
+            let line = line.to_string();
+            if filter.as_ref().is_none_or(|f| f.matches(path)) && !patterns.contains(&line) {
+                patterns.push(line);
+            }
+        }
+    }
+    if patterns.is_empty() {
+        bail!("No patterns to copy from {}", source.display());
+    }
+    info!(
+        "Copying {} patterns from {}",
+        patterns.len(),
+        source.display()
+    );
+
+    let mut copy_args = args.clone();
+    copy_args.command = None;
+    copy_args.inputs = Some(vec![patterns.join("\n")]);
+    copy_args.absolute = true;
+    add(&copy_args)
+}
+
+/// Names of directories that build tools and package managers create
+const ARTIFACTS: [&str; 7] = [
+    "node_modules",
+    "target",
+    ".venv",
+    "__pycache__",
+    "build",
+    "dist",
+    "DerivedData",
+];
+
+/// Adds directories of `dir` (`rel` to the folder root) named like build
+/// artifacts that `matcher` doesn't ignore to `found`, with their sizes
+fn find_artifacts(
+    dir: &Path,
+    rel: &str,
+    matcher: &matcher::Matcher,
+    found: &mut Vec<matcher::Entry>,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        debug!("Can't read {}", dir.display());
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        // symlinks aren't followed
+        if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if rel.is_empty() {
+            name.clone()
+        } else {
+            format!("{rel}/{name}")
+        };
+        if matcher.is_ignored(&path) {
+            continue;
+        }
+        if ARTIFACTS.contains(&name.as_str()) {
+            let (size, files) = matcher::dir_size(&entry.path(), &mut Vec::new());
+            found.push(matcher::Entry {
+                path,
+                dir: true,
+                ignored: false,
+                size,
+                files,
+            });
+        } else {
+            find_artifacts(&entry.path(), &path, matcher, found);
+        }
+    }
+}
+
+/// Files and directories of the directory `rel` (relative to the root of the
+/// folder `st_dir`) with the size of data `matcher` doesn't ignore in them,
+/// largest first
+fn largest_entries(st_dir: &Path, rel: &str, matcher: &matcher::Matcher) -> Vec<matcher::Entry> {
+    let mut entries: BTreeMap<String, matcher::Entry> = BTreeMap::new();
+    let unreadable = matcher::walk(st_dir, matcher, &mut |file| {
+        if file.ignored {
+            return;
+        }
+        let inside = match rel {
+            "" => Some(file.path.as_str()),
+            rel => file
+                .path
+                .strip_prefix(rel)
+                .and_then(|p| p.strip_prefix('/')),
+        };
+        let Some(inside) = inside else {
+            return;
+        };
+        let (name, dir) = match inside.split_once('/') {
+            Some((name, _)) => (name, true),
+            None => (inside, false),
+        };
+        let path = match rel {
+            "" => name.to_string(),
+            rel => format!("{rel}/{name}"),
+        };
+        let entry = entries.entry(path.clone()).or_insert(matcher::Entry {
+            path,
+            dir,
+            ignored: false,
+            size: 0,
+            files: 0,
+        });
+        entry.size += file.size;
+        entry.files += file.files;
+    });
+    for path in unreadable {
+        debug!("Can't read {}, not counting it", path.display());
+    }
+    let mut entries: Vec<_> = entries.into_values().collect();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.size));
+    entries
+}
+
+fn suggest(args: &Args, suggest_args: &SuggestArgs) -> Result<()> {
+    let (st_dir, prefix) = find_syncthing_dir(args)?;
+    let matcher = load_matcher(args, &st_dir)?;
+    let rel: PathBuf = prefix
+        .components()
+        .filter(|c| matches!(c, path::Component::Normal(_)))
+        .collect();
+    let rel = winpath::to_pattern(&rel)?;
+    let mut found = Vec::new();
+    if let Some(n) = suggest_args.by_size {
+        found = largest_entries(&st_dir, &rel, &matcher);
+        found.truncate(n);
+    } else {
+        find_artifacts(&st_dir.join(&rel), &rel, &matcher, &mut found);
+    }
+    if found.is_empty() {
+        if !args.quiet() {
+            match suggest_args.by_size {
+                Some(_) => println!("Everything in the current directory is already ignored."),
+                None => println!("No build artifacts that aren't ignored yet."),
+            }
+        }
+        return Ok(());
+    }
+    let palette = color::Palette::stdout(args.color);
+    let page = suggest_args.page;
+    let (found, left) = page.page(&found);
+    if suggest_args.by_owner || suggest_args.by_age {
+        self::page(args, &breakdown(args, suggest_args, &st_dir, found));
+        page.note_left(args, left, found.len());
+        return Ok(());
+    }
+    let ask_user = is_interactive() && !args.yes;
+    if !ask_user && !args.yes {
+        let text: String = found
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{:>10}  {}{}\n",
+                    stats::human_size(entry.size),
+                    palette.link(&entry.path, &st_dir.join(&entry.path)),
+                    if entry.dir { "/" } else { "" }
+                )
+            })
+            .collect();
+        self::page(args, &text);
+        page.note_left(args, left, found.len());
+        println!("Run in a terminal to choose which of them to ignore, or pass --yes to ignore all of them.");
+        return Ok(());
+    }
+    let mut patterns = Vec::new();
+    for entry in found {
+        let pattern = format!("/{}", syntax::escape(&entry.path));
+        let size = stats::human_size(entry.size);
+        if !ask_user || ask(&format!("Ignore {pattern} ({size})?")) {
+            patterns.push(pattern);
+        }
+    }
+    page.note_left(args, left, found.len());
+    if patterns.is_empty() {
+        return Ok(());
+    }
+    let mut suggest_args = args.clone();
+    suggest_args.command = None;
+    suggest_args.inputs = Some(vec![patterns.join("\n")]);
+    suggest_args.absolute = true;
+    add(&suggest_args)
+}
+
+/// Suggestions `found` in the folder `st_dir` grouped by owner and/or last
+/// access, each group followed by a command that ignores it
+fn breakdown(
+    args: &Args,
+    suggest_args: &SuggestArgs,
+    st_dir: &Path,
+    found: &[matcher::Entry],
+) -> String {
+    let palette = color::Palette::stdout(args.color);
+    let now = std::time::SystemTime::now();
+    let mut groups: BTreeMap<(String, Option<owners::Age>), Vec<_>> = BTreeMap::new();
+    for entry in found {
+        let path = st_dir.join(&entry.path);
+        let accessed = owners::last_access(&path);
+        let owner = match suggest_args.by_owner {
+            true => owners::owner(&path).unwrap_or_else(|| "unknown owner".to_string()),
+            false => String::new(),
+        };
+        let age = suggest_args.by_age.then(|| owners::Age::of(accessed, now));
+        groups
+            .entry((owner, age))
+            .or_default()
+            .push((entry, accessed));
+    }
+    let size = |entries: &[(&matcher::Entry, _)]| entries.iter().map(|(e, _)| e.size).sum::<u64>();
+    let mut owner_sizes: BTreeMap<String, u64> = BTreeMap::new();
+    for ((owner, _), entries) in &groups {
+        *owner_sizes.entry(owner.clone()).or_default() += size(entries);
+    }
+    // owners with the most data first, the oldest data of each owner first
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_key(|((owner, age), _)| (std::cmp::Reverse(owner_sizes[owner]), *age));
+    let mut text = String::new();
+    for (n, ((owner, age), entries)) in groups.iter().enumerate() {
+        if n > 0 {
+            text.push('\n');
+        }
+        let title = match age {
+            Some(age) if suggest_args.by_owner => format!("{owner}, {age}"),
+            Some(age) => {
+                let age = age.to_string();
+                match age.chars().next() {
+                    Some(first) => {
+                        format!("{}{}", first.to_uppercase(), &age[first.len_utf8()..])
+                    }
+                    None => age,
+                }
+            }
+            None => owner.clone(),
+        };
+        text.push_str(&format!(
+            "{title} ({}):\n",
+            stats::human_size(size(entries))
+        ));
+        for (entry, accessed) in entries {
+            text.push_str(&format!(
+                "  {:>10}  {}{}{}\n",
+                stats::human_size(entry.size),
+                palette.link(&entry.path, &st_dir.join(&entry.path)),
+                if entry.dir { "/" } else { "" },
+                match accessed {
+                    Some(t) if suggest_args.by_age => {
+                        format!("  accessed {}", owners::days_ago(*t, now))
+                    }
+                    _ => String::new(),
+                }
+            ));
+        }
+        let patterns: Vec<_> = entries
+            .iter()
+            .map(|(e, _)| emit::quote(&format!("/{}", syntax::escape(&e.path))))
+            .collect();
+        text.push_str(&format!(
+            "  stignore --root {} -a -- {}\n",
+            emit::quote(&st_dir.display().to_string()),
+            patterns.join(" ")
+        ));
+    }
+    text
+}
+
+/// Runs `go` for every folder from syncthing's config.xml, going on after failures
+fn go_all_folders(args: &Args) -> Result<()> {
+    let config = syncthing::Config::load()?;
+    if config.folders.is_empty() {
+        bail!("There are no folders in {}", config.path.display());
+    }
+    let mut folder_args = args.clone();
+    folder_args.inputs = Some(pattern_inputs(args)?);
+    folder_args.absolute = true;
+    folder_args.all_folders = false;
+
+    let mut reports = Vec::new();
+    let mut failed = 0;
+    for folder in &config.folders {
+        if !args.quiet() {
+            let name = if folder.label.is_empty() {
+                &folder.id
+            } else {
+                &folder.label
+            };
+            println!("{name} ({}):", folder.path.display());
+        }
+        folder_args.root = Some(folder.path.clone());
+        let mut report = Report::default();
+        if let Err(e) = go(&folder_args, &mut report) {
+            if !args.quiet() {
+                eprintln!(
+                    "{} {e:#}",
+                    color::Palette::stderr(args.color).error("Error:")
+                );
+            }
+            report.folder.get_or_insert_with(|| folder.path.clone());
+            report.errors.push(format!("{e:#}"));
+            failed += 1;
+        }
+        reports.push(report);
+    }
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&reports).expect("report is serializable")
+        );
+    }
+    if failed > 0 {
+        bail!(
+            "Patterns weren't added to {failed} of {} folders",
+            config.folders.len()
+        );
+    }
+    Ok(())
+}
+
+/// Removes lines of `patterns` that are already present in `existing` or
+/// earlier in `patterns` itself, returns the removed ones
+fn skip_present(patterns: &mut String, existing: &str) -> Vec<String> {
+    let mut present: std::collections::HashSet<_> = existing.lines().map(str::trim).collect();
+    let mut skipped = Vec::new();
+    let mut kept = String::new();
+    for line in patterns.lines() {
+        let pattern = line.trim();
+        let is_pattern = !pattern.is_empty() && !pattern.starts_with("//");
+        if is_pattern && !present.insert(pattern) {
+            skipped.push(pattern.to_string());
+            continue;
+        }
+        kept.push_str(line);
+        kept.push_str(LINE_ENDING);
+    }
+    *patterns = kept;
+    skipped
+}
+
+/// Files of the folder `st_dir` that aren't ignored by its current patterns,
+/// with their sizes
+fn present_files(args: &Args, st_dir: &Path) -> Result<Vec<(String, u64)>> {
+    let matcher = load_matcher(args, st_dir)?;
+    let mut files = Vec::new();
+    let unreadable = matcher::walk(st_dir, &matcher, &mut |entry| {
+        if !entry.ignored {
+            files.push((entry.path, entry.size));
+        }
+    });
+    for path in unreadable {
+        debug!("Can't read {}, not listing its matches", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// How many of `present` files each of the processed `patterns` would ignore
+/// and their total size. With `list` the files are listed too, directories
+/// matched as a whole once, with a trailing `/`
+fn describe_matches(present: &[(String, u64)], patterns: &str, list: bool) -> String {
+    const SHOWN: usize = 20;
+    let mut out = String::new();
+    for line in patterns.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+        let Ok(rule) = matcher::Rule::parse(line) else {
+            continue;
+        };
+        if rule.negated {
+            continue;
+        }
+        let mut matched: Vec<String> = Vec::new();
+        let (mut files, mut size) = (0, 0);
+        for (path, bytes) in present {
+            // the outermost matching directory stands for everything inside
+            let outermost = path
+                .match_indices('/')
+                .map(|(n, _)| &path[..n])
+                .find(|dir| rule.matches(dir));
+            let shown = match outermost {
+                Some(dir) => format!("{dir}/"),
+                None if rule.matches(path) => path.clone(),
+                None => continue,
+            };
+            files += 1;
+            size += bytes;
+            if matched.last() != Some(&shown) {
+                matched.push(shown);
+            }
+        }
+        if matched.is_empty() {
+            out.push_str(&format!("{line} matches nothing yet\n"));
+            continue;
+        }
+        out.push_str(&format!(
+            "{line} matches {files} file{} ({}){}\n",
+            if files > 1 { "s" } else { "" },
+            stats::human_size(size),
+            if list { ":" } else { "" }
+        ));
+        if !list {
+            continue;
+        }
+        for path in matched.iter().take(SHOWN) {
+            out.push_str(&format!("  {path}\n"));
+        }
+        if matched.len() > SHOWN {
+            out.push_str(&format!("  ...and {} more\n", matched.len() - SHOWN));
+        }
+    }
+    out
+}
+
+/// Removes patterns that are covered by existing patterns of the folder
+/// `st_dir` from `patterns`, reporting them
+fn skip_redundant(args: &Args, st_dir: &Path, patterns: &mut String, report: &mut Report) {
+    let matcher = match load_matcher(args, st_dir) {
+        Ok(matcher) => matcher,
+        Err(e) => {
+            debug!("Not checking for redundant patterns: {e:#}");
+            return;
+        }
+    };
+    let mut kept = String::new();
+    for line in patterns.lines() {
+        let Some(rule) = covering_rule(&matcher, line.trim()) else {
+            kept.push_str(line);
+            kept.push_str(LINE_ENDING);
+            continue;
+        };
+        let covered_by = match &rule.location {
+            Some((file, n)) => format!("{} ({}:{n})", rule.line, file.display()),
+            None => rule.line.clone(),
+        };
+        if !args.quiet() {
+            eprintln!(
+                "{} skipping {}, everything it matches is already ignored by {covered_by}. \
+                Pass --allow-redundant to add it anyway",
+                color::Palette::stderr(args.color).note("NOTE:"),
+                line.trim()
+            );
+        }
+        report.skipped.push(line.trim().to_string());
+    }
+    *patterns = kept;
+    report.patterns = patterns.lines().map(String::from).collect();
+}
+
+/// Warns about `patterns` that would never take effect after being appended
+/// to `target`, because an earlier rule always matches first
+fn warn_about_shadowed(
+    args: &Args,
+    st_dir: &Path,
+    target: &Path,
+    patterns: &str,
+    report: &mut Report,
+) {
+    let Ok(matcher) = load_matcher(args, st_dir) else {
+        return;
+    };
+    let rules = matcher.rules();
+    let in_target = |r: &matcher::Rule| r.location.as_ref().is_some_and(|(f, _)| f == target);
+    let end = if args.prepend || args.before_includes {
+        // inserted patterns are checked before the target's rules below the insertion line
+        let old = corrupt::read(args.backend(), target).unwrap_or_default();
+        let line = insertion_line(&old, args.before_includes).unwrap_or(usize::MAX);
+        let first = rules.iter().position(in_target);
+        let before = rules.iter().rposition(|r| {
+            r.location
+                .as_ref()
+                .is_some_and(|(f, n)| f == target && *n <= line)
+        });
+        match (before, first) {
+            (Some(n), _) => Some(n + 1),
+            (None, Some(n)) => Some(n),
+            (None, None) if target == st_dir.join(".stignore") => Some(0),
+            (None, None) => None,
+        }
+    } else if target == st_dir.join(".stignore") {
+        // appended patterns are checked right after the last rule of the target
+        Some(rules.len())
+    } else {
+        rules.iter().rposition(in_target).map(|n| n + 1)
+    };
+    let Some(end) = end else {
+        return;
+    };
+    let mut added = Vec::new();
+    for line in patterns.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+        let Ok(rule) = matcher::Rule::parse(line) else {
+            continue;
+        };
+        let shadowed =
+            lint::shadowed_by(&rules[..end], &rule).or_else(|| lint::shadowed_by(&added, &rule));
+        if let Some(by) = shadowed {
+            let at = match &by.location {
+                Some((file, n)) => format!(" ({}:{n})", file.display()),
+                None => String::new(),
+            };
+            let note = format!(
+                "{line} will never take effect, {}{at} matches first. \
+                Move it above that pattern with `stignore edit` or add it with --prepend",
+                by.line
+            );
+            if !args.quiet() {
+                eprintln!(
+                    "{} {note}",
+                    color::Palette::stderr(args.color).note("NOTE:")
+                );
+            }
+            report.warnings.push(note);
+        }
+        added.push(rule);
+    }
+}
+
+/// Existing rule of `matcher` that already ignores everything `pattern` would.
+/// Only checks rooted patterns without wildcards, `None` if coverage can't be proven
+fn covering_rule<'a>(matcher: &'a matcher::Matcher, pattern: &str) -> Option<&'a matcher::Rule> {
+    let (prefix, path) = syntax::split_modifiers(pattern);
+    let modifiers = syntax::Modifiers::parse(prefix);
+    let path = path.strip_prefix('/')?.trim_end_matches('/');
+    if modifiers.negated || path.is_empty() || !syntax::is_literal(path) {
+        return None;
+    }
+    let rule = matcher.rule_for(path)?;
+    let same_effect = !rule.negated
+        && (rule.deletable || !modifiers.deletable)
+        && (rule.case_insensitive || !modifiers.case_insensitive);
+    // a negation could un-ignore something inside of `path`
+    let may_unignore = matcher.rules().iter().filter(|r| r.negated).any(|r| {
+        let (_, negated) = syntax::split_modifiers(&r.line);
+        let Some(rooted) = negated.strip_prefix('/') else {
+            return true;
+        };
+        let literal = &rooted[..rooted
+            .find(['*', '?', '[', '{', '\\'])
+            .unwrap_or(rooted.len())];
+        literal.starts_with(path) || path.starts_with(literal)
+    });
+    (same_effect && !may_unignore).then_some(rule)
+}
+
+/// Asks syncthing to rescan the folder `st_dir`, waits until the scan is done
+/// and reports how the amount of local and global data changed
+fn rescan(args: &Args, st_dir: &Path) -> Result<()> {
+    let config = syncthing::Config::load()?;
+    let folder = shared_folder(&config, st_dir)?;
+    let api = local_api(args, &config)?;
+    let before = api.db_status(&folder.id)?;
+    api.scan(&folder.id)?;
+    info!("Requested rescan of folder {}", folder.id);
+
+    let progress = !args.quiet() && std::io::stderr().is_terminal();
+    let started = std::time::Instant::now();
+    let timeout = std::time::Duration::from_secs(args.rescan_timeout);
+    let after = loop {
+        let status = api.db_status(&folder.id)?;
+        debug!("Folder {} is {}", folder.id, status.state);
+        if status.state == "idle" {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            if progress {
+                eprintln!();
+            }
+            bail!(
+                "Syncthing didn't finish scanning {} in {}s, it's still {}",
+                st_dir.display(),
+                args.rescan_timeout,
+                status.state
+            );
+        }
+        if progress {
+            eprint!(
+                "\rWaiting for syncthing: {} ({}s)",
+                status.state,
+                started.elapsed().as_secs()
+            );
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    };
+    if progress && started.elapsed() >= std::time::Duration::from_millis(500) {
+        eprintln!();
+    }
+
+    if !args.quiet() {
+        let data = |files, bytes| format!("{files} files ({})", stats::human_size(bytes));
+        println!("Rescan finished:");
+        println!(
+            "  local:  {} -> {}",
+            data(before.local_files, before.local_bytes),
+            data(after.local_files, after.local_bytes)
+        );
+        println!(
+            "  global: {} -> {}",
+            data(before.global_files, before.global_bytes),
+            data(after.global_files, after.global_bytes)
+        );
+    }
+    Ok(())
+}
+
+/// Folder of syncthing's `config` with the root `st_dir`
+fn shared_folder<'a>(
+    config: &'a syncthing::Config,
+    st_dir: &Path,
+) -> Result<&'a syncthing::Folder> {
+    config.folder(st_dir).with_context(|| {
+        format!(
+            "{} isn't shared in {}",
+            st_dir.display(),
+            config.path.display()
+        )
+    })
+}
+
+/// Offers to move corrupt ignore files of `st_dir` that patterns could be
+/// appended to out of the way, so a fresh file is started instead
+fn quarantine_corrupt(args: &Args, st_dir: &Path, report: &mut Report) -> Result<()> {
+    let stignore = st_dir.join(".stignore");
+    quarantine_if_corrupt(args, st_dir, &stignore, report)?;
+    if let Some(file) = &args.file {
+        return quarantine_if_corrupt(args, st_dir, &st_dir.join(file), report);
+    }
+    let sync_target = match args.target {
+        Target::Stignore => false,
+        Target::StignoreSync | Target::Both => true,
+        Target::Auto => is_stignore_sync_included(args.backend(), &stignore)?,
+    };
+    if sync_target {
+        quarantine_if_corrupt(args, st_dir, &st_dir.join(".stignore_sync"), report)?;
+    }
+    Ok(())
+}
+
+fn quarantine_if_corrupt(
+    args: &Args,
+    st_dir: &Path,
+    path: &Path,
+    report: &mut Report,
+) -> Result<()> {
+    let backend = args.backend();
+    let Err(err) = read_ignore_file(backend, path) else {
+        return Ok(());
+    };
+    let Some(Failure::Corrupt { damage, .. }) = err.downcast_ref::<Failure>() else {
+        return Err(err);
+    };
+    let dir = path.parent().unwrap_or(path);
+    let ask = args.backup == Backup::Ask;
+    if args.backup == Backup::Never
+        || (ask && !args.yes && !is_interactive())
+        || is_read_only_mount(dir)
+    {
+        return Err(err);
+    }
+    // a fresh file drops every pattern of the corrupt one
+    let old = backend.read(path)?.unwrap_or_default();
+    enforce_policy(backend, st_dir, path, &String::from_utf8_lossy(&old), "")?;
+    let backup = corrupt::backup_path(backend, path);
+    if !args.quiet() {
+        eprintln!(
+            "{} {} looks corrupted: {damage}",
+            color::Palette::stderr(args.color).error("WARNING:"),
+            path.display()
+        );
+    }
+    if ask {
+        confirm(
+            args,
+            &format!("Move it to {} and start a fresh one?", backup.display()),
+        )?;
+    }
+    corrupt::quarantine(backend, path, &backup)?;
+    let warning = format!("Moved corrupt {} to {}", path.display(), backup.display());
+    if !args.quiet() {
+        println!("{warning}");
+    }
+    report.warnings.push(warning);
+    Ok(())
+}
+
+/// Globs that the shell seems to have expanded: at least 3 of `names` are
+/// all the files of one directory with the same extension.
+/// Returns each glob with indexes of names it was expanded into
+fn expanded_globs(names: &[String]) -> Vec<(String, Vec<usize>)> {
+    let mut groups: BTreeMap<(PathBuf, String), Vec<usize>> = BTreeMap::new();
+    for (n, name) in names.iter().enumerate() {
+        let path = Path::new(name);
+        let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+            continue;
+        };
+        if path.is_file() {
+            let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+            groups.entry((dir, ext.to_string())).or_default().push(n);
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, indexes)| indexes.len() >= 3)
+        .filter(|((dir, ext), indexes)| {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                return false;
+            };
+            // shell globs skip hidden files
+            let matching = entries
+                .flatten()
+                .filter(|e| {
+                    let name = e.file_name().to_string_lossy().into_owned();
+                    !name.starts_with('.')
+                        && Path::new(&name)
+                            .extension()
+                            .is_some_and(|e| e == ext.as_str())
+                })
+                .count();
+            matching == indexes.len()
+        })
+        .map(|((dir, ext), indexes)| {
+            let glob = format!("*.{}", syntax::escape(&ext));
+            let glob = if dir.as_os_str().is_empty() {
+                glob
+            } else {
+                format!("{}/{glob}", syntax::escape(&dir.to_string_lossy()))
+            };
+            (glob, indexes)
+        })
+        .collect()
+}
+
+/// Offers to replace file names that the shell expanded a glob into with
+/// the glob itself, warns about them outside of a terminal
+fn collapse_expanded_globs(
+    args: &Args,
+    inputs: &mut Vec<String>,
+    report: &mut Report,
+) -> Result<()> {
+    // inputs start with the arguments, `@FILE`s and `-` don't come from the shell
+    let names: Vec<_> = args
+        .pattern
+        .iter()
+        .map(|p| {
+            if p.starts_with('@') || p == "-" {
+                String::new()
+            } else {
+                p.clone()
+            }
+        })
+        .collect();
+    let mut replaced = Vec::new();
+    for (glob, indexes) in expanded_globs(&names) {
+        let note = format!(
+            "Looks like the shell expanded {glob} into {} file names, \
+            quote the pattern to add it as a glob: '{glob}'",
+            indexes.len()
+        );
+        if !args.quiet() {
+            eprintln!(
+                "{} {note}",
+                color::Palette::stderr(args.color).note("NOTE:")
+            );
+        }
+        if args.yes || !is_interactive() {
+            report.warnings.push(note);
+            continue;
+        }
+        if ask(&format!("Add {glob} instead of {} names?", indexes.len())) {
+            inputs[indexes[0]] = glob;
+            replaced.extend_from_slice(&indexes[1..]);
+        }
+    }
+    replaced.sort_unstable();
+    for n in replaced.into_iter().rev() {
+        inputs.remove(n);
+    }
+    Ok(())
+}
+
+/// Patterns matching exactly the existing `paths` inside of the folder `st_dir`
+fn path_patterns(args: &Args, st_dir: &Path, paths: &[String]) -> Result<Vec<String>> {
+    let cwd = current_dir(args)?;
+    let mut patterns = Vec::new();
+    for input in paths
+        .iter()
+        .flat_map(|p| p.lines())
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+    {
+        let path = cwd.join(input);
+        std::fs::symlink_metadata(&path).with_context(|| format!("{input} doesn't exist"))?;
+        // symlinks are matched themselves, not their targets
+        let path = match (path.parent(), path.file_name()) {
+            _ if args.no_resolve_symlinks => normalize(&path),
+            (Some(parent), Some(name)) => winpath::canonicalize(parent)?.join(name),
+            _ => winpath::canonicalize(&path)?,
+        };
+        let Ok(relative) = path.strip_prefix(st_dir) else {
+            bail!(
+                "{input} is outside of syncthing folder {}",
+                st_dir.display()
+            );
+        };
+        if relative.as_os_str().is_empty() {
+            bail!("{input} is the root of syncthing folder, it can't be ignored");
+        }
+        let components: Vec<_> = relative
+            .components()
+            .map(|c| winpath::utf8(c.as_os_str()).map(syntax::escape))
+            .collect::<Result<_>>()?;
+        patterns.push(format!("/{}", components.join("/")));
+    }
+    Ok(patterns)
+}
+
+/// Patterns from arguments (with `@FILE`s and `-` expanded) and --from-file files
+fn pattern_inputs(args: &Args) -> Result<Vec<String>> {
+    if let Some(inputs) = &args.inputs {
+        return Ok(inputs.clone());
+    }
+    let read = |path: &Path| {
+        if path == Path::new("-") {
+            let mut patterns = String::new();
+            std::io::stdin()
+                .read_to_string(&mut patterns)
+                .context("Can't read patterns from stdin")?;
+            return Ok(patterns.trim_end_matches(['\r', '\n']).to_string());
+        }
+        std::fs::read_to_string(path)
+            // trailing new line would add an empty line
+            .map(|p| p.trim_end_matches(['\r', '\n']).to_string())
+            .with_context(|| format!("Can't read patterns from {}", path.display()))
+    };
+    let mut inputs = Vec::new();
+    for pattern in &args.pattern {
+        match pattern.strip_prefix('@') {
+            Some(file) => inputs.push(read(Path::new(file))?),
+            None if pattern == "-" => inputs.push(read(Path::new("-"))?),
+            None => inputs.push(pattern.clone()),
+        }
+    }
+    for file in &args.from_file {
+        inputs.push(read(file)?);
+    }
+    if args.from_clipboard {
+        inputs.push(clipboard()?);
+    }
+    Ok(inputs)
+}
+
+/// Text in the system clipboard, read with the platform's clipboard tool
+fn clipboard() -> Result<String> {
+    let tools: &[(&str, &[&str])] = if cfg!(windows) {
+        &[(
+            "powershell",
+            &["-NoProfile", "-Command", "Get-Clipboard -Raw"],
+        )]
+    } else if cfg!(target_os = "macos") {
+        &[("pbpaste", &[])]
+    } else {
+        &[
+            ("wl-paste", &["--no-newline"]),
+            ("xclip", &["-selection", "clipboard", "-out"]),
+            ("xsel", &["--clipboard", "--output"]),
+        ]
+    };
+    tools
+        .iter()
+        .inspect(|(program, _)| debug!("Reading clipboard with {program}"))
+        .find_map(|(program, args)| command_output(program, args))
+        .with_context(|| {
+            format!(
+                "Can't read the clipboard, make sure that one of {} is installed",
+                tools
+                    .iter()
+                    .map(|(program, _)| *program)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+}
+
+/// Patterns that ignore everything are sometimes used to keep files deleted
+/// on other devices, suggests the ignoreDelete option instead
+fn warn_about_ignore_delete(args: &Args, inputs: &[String], report: &mut Report) {
+    let everything = inputs
+        .iter()
+        .flat_map(|p| p.split('\n'))
+        .map(|p| syntax::split_modifiers(p.trim()))
+        .find(|(modifiers, pattern)| {
+            !modifiers.contains('!')
+                && !modifiers.contains("(?d)")
+                && syntax::matches_everything(pattern)
+        });
+    if let Some((modifiers, pattern)) = everything {
+        let note = format!(
+            "{modifiers}{pattern} ignores everything{}, so it will stop syncing completely. \
+            If you want to keep files that get deleted on other devices, \
+            use the folder's ignoreDelete option instead: `stignore ignore-delete on`",
+            if args.absolute {
+                ""
+            } else {
+                " in the current directory"
+            }
+        );
+        if !args.quiet() {
+            eprintln!(
+                "{} {note}",
+                color::Palette::stderr(args.color).note("NOTE:")
+            );
+        }
+        report.warnings.push(note);
+    }
+}
+
+/// Processed `patterns` that ignore everything in the folder
+fn catch_alls(patterns: &str) -> Vec<&str> {
+    patterns
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("//") && !l.starts_with('#'))
+        .filter(|l| {
+            let (modifiers, pattern) = syntax::split_modifiers(l);
+            !modifiers.contains('!') && syntax::matches_everything(pattern)
+        })
+        .collect()
+}
+
+/// Makes sure that patterns ignoring everything in the folder are intended:
+/// asks in a terminal, fails otherwise. --force skips the question, --yes doesn't
+fn confirm_catch_all(args: &Args, patterns: &str) -> Result<()> {
+    use question::{Answer, Question};
+    let all = catch_alls(patterns);
+    if all.is_empty() || args.force {
+        return Ok(());
+    }
+    let problem = format!(
+        "{} ignores everything in the folder, so syncing it would stop completely",
+        all.join(", ")
+    );
+    // --fast-append never stops to ask
+    if !is_interactive() || args.fast_append {
+        bail!(Failure::InvalidPatterns(vec![format!(
+            "{problem}. Pass --force to add it anyway"
+        )]));
+    }
+    let answer = Question::new(&format!("{problem}. Add it anyway?"))
+        .until_acceptable()
+        .default(Answer::NO)
+        .show_defaults()
+        .confirm();
+    if answer != Answer::YES {
+        bail!(Failure::Aborted);
+    }
+    Ok(())
+}
+
+/// Markers and ignore files of the folder `st_dir`, relative to its root
+fn internal_paths(args: &Args, st_dir: &Path) -> Result<Vec<String>> {
+    // --fast-append doesn't follow #includes
+    let mut files = if args.fast_append {
+        vec![st_dir.join(".stignore")]
+    } else {
+        ignore_files(args.backend(), st_dir)
+    };
+    files.push(st_dir.join(".stignore_sync"));
+    files.extend(args.file.iter().map(|f| st_dir.join(f)));
+    files.extend(args.routes.iter().map(|r| st_dir.join(&r.file)));
+    let mut internal = markers(args).to_vec();
+    for file in files {
+        if let Ok(relative) = normalize(&file).strip_prefix(st_dir) {
+            let relative = winpath::to_pattern(relative)?;
+            if !internal.contains(&relative) {
+                internal.push(relative);
+            }
+        }
+    }
+    Ok(internal)
+}
+
+/// Fails if `patterns` would ignore the folder marker or one of the ignore
+/// files, which breaks syncing in confusing ways, unless --force is passed.
+/// Patterns that ignore everything are confirmed by [`confirm_catch_all`]
+fn refuse_internal(args: &Args, st_dir: &Path, patterns: &str) -> Result<()> {
+    if args.force {
+        return Ok(());
+    }
+    let internal = internal_paths(args, st_dir)?;
+    let mut errs = Vec::new();
+    let catch_alls = catch_alls(patterns);
+    for line in patterns.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+        if catch_alls.contains(&line) {
+            continue;
+        }
+        let Ok(rule) = matcher::Rule::parse(line) else {
+            continue;
+        };
+        if rule.negated {
+            continue;
+        }
+        if let Some(path) = internal.iter().find(|p| rule.matches(p)) {
+            errs.push(format!(
+                "{line}: ignores {path}, which breaks syncing of the folder. \
+                Pass --force to add it anyway"
+            ));
+        }
+    }
+    if !errs.is_empty() {
+        bail!(Failure::InvalidPatterns(errs));
+    }
+    Ok(())
+}
+
+/// Shows what `path` would look like with `patterns` appended, so the
+/// changes can be made where the folder is writable
+fn print_read_only_fallback(args: &Args, path: &Path, patterns: &str) {
+    let mut contents = args
+        .backend()
+        .read(path)
+        .ok()
+        .flatten()
+        .map(|c| String::from_utf8_lossy(&c).into_owned())
+        .unwrap_or_default();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push_str(LINE_ENDING);
+    }
+    contents.push_str(patterns);
+    page(
+        args,
+        &format!(
+            "{} can't be modified: the folder is mounted read-only.\n\
+            Append these patterns on a device where the folder is writable:\n\
+            {patterns}\n\
+            Resulting contents of {}:\n\
+            {contents}",
+            path.display(),
+            path.display(),
+        ),
+    );
+}
+
+/// Asks whether to proceed with appending `patterns`.
+///
+/// Returns edited patterns if user chose to edit them (and has to be asked again)
+fn preview_prompt(patterns: &str) -> Result<Option<String>> {
+    use question::{Answer, Question};
+    let answer = Question::new("Proceed? (Y/n/e - edit patterns)")
+        .acceptable(vec!["y", "yes", "n", "no", "e", "edit"])
+        .until_acceptable()
+        .default(Answer::YES)
+        .ask();
+    let answer = match answer {
+        Some(Answer::RESPONSE(r)) => r,
+        _ => return Ok(None),
+    };
+    match answer.as_str() {
+        "n" | "no" => bail!(Failure::Aborted),
+        "e" | "edit" => edit_patterns(patterns).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Lets user edit `patterns` in the editor
+fn edit_patterns(patterns: &str) -> Result<String> {
+    let path = std::env::temp_dir().join(format!("stignore-{}.stignore", std::process::id()));
+    std::fs::write(&path, patterns).context("Can't create temporary file")?;
+    let edited = run_editor(&path)
+        .and_then(|_| std::fs::read_to_string(&path).context("Can't read edited patterns"));
+    let _ = std::fs::remove_file(&path);
+
+    let mut out = String::new();
+    for line in edited?.lines() {
+        out.push_str(line);
+        out.push_str(LINE_ENDING);
+    }
+    if out.trim().is_empty() {
+        bail!(Failure::NothingToDo);
+    }
+    Ok(out)
+}
+
+/// Fills options that weren't passed on the command line or in environment
+/// variables from config files
+fn apply_config(args: &mut Args, matches: &ArgMatches) -> Result<()> {
+    let folder = match &args.root {
+        Some(root) => Some(root.clone()),
+        None => current_dir(args).ok().and_then(|cwd| {
+            let mut folders = find_folders(&cwd, markers(args));
+            if args.outermost {
+                folders.pop()
+            } else {
+                folders.into_iter().next()
+            }
+        }),
+    };
+    let config = config::Config::load(folder.as_deref(), args.profile.as_deref())?;
+
+    let mut matches = matches;
+    let mut sources = vec![matches];
+    while let Some((_, sub)) = matches.subcommand() {
+        sources.push(sub);
+        matches = sub;
+    }
+    // subcommands don't have all of the arguments
+    let unset = |id: &str| {
+        !sources.iter().any(|m| {
+            m.try_contains_id(id).is_ok()
+                && matches!(
+                    m.value_source(id),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+        })
+    };
+
+    if let (Some(target), true) = (config.target, unset("target")) {
+        args.target = target;
+    }
+    args.routes = config.routes.unwrap_or_default();
+    args.remotes = config.remotes.unwrap_or_default();
+    args.api = config.api;
+    if let Some(fragment) = &args.fragment {
+        args.file = Some(fragments::path(fragment)?);
+    }
+    if args.file.is_some() {
+        // --file overrides --target
+        args.target = Target::Auto;
+    }
+    if let (Some(absolute), true) = (config.absolute, unset("absolute")) {
+        args.absolute = absolute;
+    }
+    if let (Some(preview), true) = (config.preview, unset("preview") && !args.quiet()) {
+        args.preview = preview;
+    }
+    if let (Some(non_interactive), true) = (config.non_interactive, unset("non-interactive")) {
+        args.non_interactive = non_interactive;
+    }
+    if let (Some(color), true) = (config.color, unset("color")) {
+        args.color = color;
+    }
+    if let (Some(pager), true) = (config.pager, unset("no-pager")) {
+        args.no_pager = !pager;
+    }
+    if let (Some(auto), true) = (config.auto_case_insensitive, unset("auto-case-insensitive")) {
+        args.auto_case_insensitive = auto;
+    }
+    if let (Some(fsync), true) = (config.fsync, unset("fsync")) {
+        args.fsync = fsync;
+    }
+    if let (Some(backend), true) = (config.backend, unset("backend")) {
+        args.backend = backend;
+    }
+    if let (Some(backup), true) = (config.backup, unset("backup")) {
+        args.backup = backup;
+    }
+    if let (Some(form), true) = (config.unicode_form, unset("unicode-form")) {
+        args.unicode_form = form;
+    }
+    if let (Some(provenance), true) = (config.provenance, unset("provenance")) {
+        args.provenance = provenance;
+    }
+    Ok(())
+}
+
+/// Makes --cwd the current directory, so everything works as if stignore was run there
+fn change_dir(args: &Args) -> Result<()> {
+    if let Some(dir) = &args.cwd {
+        let logical = resolve_dir(args, dir);
+        std::env::set_current_dir(dir)
+            .with_context(|| format!("Can't change directory to {}", dir.display()))?;
+        if let (true, Ok(logical)) = (args.no_resolve_symlinks, logical) {
+            std::env::set_var("PWD", logical);
+        }
+        info!("Working in {}", dir.display());
+    }
+    Ok(())
+}
+
+/// First argument of `argv` if it's a subcommand that is also the name of a
+/// file or directory in the current directory, which the user might have
+/// wanted to ignore instead
+fn ambiguous_subcommand(argv: &[std::ffi::OsString]) -> Option<String> {
+    let word = argv.get(1)?.to_str()?;
+    let command = Args::command();
+    let is_subcommand = command.get_subcommands().any(|c| c.get_name() == word);
+    (is_subcommand && Path::new(word).symlink_metadata().is_ok()).then(|| word.to_string())
+}
+
+fn main() -> ExitCode {
+    let argv: Vec<_> = std::env::args_os().collect();
+    let ambiguous = ambiguous_subcommand(&argv);
+    let hint = |word: &str| {
+        format!("{word} is a subcommand, to ignore ./{word} instead run `stignore -- {word}`")
+    };
+    let matches = match Args::command().try_get_matches_from(&argv) {
+        Ok(matches) => matches,
+        Err(e) => {
+            let _ = e.print();
+            if let (Some(word), true) = (&ambiguous, e.use_stderr()) {
+                eprintln!("\n{}", hint(word));
+            }
+            return ExitCode::from(if e.use_stderr() { 2 } else { 0 });
+        }
+    };
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logger::init(args.verbose);
+    if let (Some(word), 2, false) = (&ambiguous, argv.len(), args.quiet()) {
+        eprintln!(
+            "{} {}",
+            color::Palette::stderr(args.color).note("NOTE:"),
+            hint(word)
+        );
+    }
+    let res = change_dir(&args)
+        // --fast-append doesn't read config files
+        .and_then(|_| match args.fast_append {
+            true => Ok(()),
+            false => apply_config(&mut args, &matches),
+        })
+        .and_then(|_| run(&args));
+    if args.backend == BackendKind::Memory && !args.quiet() {
+        print_kept_changes(args.color);
+    }
+    match res {
+        Ok(()) => ExitCode::from(Status::Success as u8),
+        Err(e) => {
+            if !args.quiet() {
+                eprintln!(
+                    "{} {e:?}",
+                    color::Palette::stderr(args.color).error("Error:")
+                );
+            }
+            ExitCode::from(Status::of(&e) as u8)
+        }
+    }
+}
+
+/// Changes of the memory backend, shared by all commands of the invocation
+fn memory_backend() -> &'static backend::Memory {
+    static MEMORY: std::sync::OnceLock<backend::Memory> = std::sync::OnceLock::new();
+    MEMORY.get_or_init(backend::Memory::on_disk)
+}
+
+/// Prints what --backend memory kept from being written
+fn print_kept_changes(color: color::ColorChoice) {
+    let changes = memory_backend().changes();
+    let palette = color::Palette::stdout(color);
+    if changes.is_empty() {
+        println!("{} no files would be changed", palette.note("NOTE:"));
+    }
+    for (path, contents) in changes {
+        match contents {
+            Some(contents) => {
+                println!(
+                    "{} {} would contain:",
+                    palette.note("NOTE:"),
+                    path.display()
+                );
+                print!("{}", String::from_utf8_lossy(&contents));
+            }
+            None => println!(
+                "{} {} would be removed",
+                palette.note("NOTE:"),
+                path.display()
+            ),
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<()> {
+    match args.command {
+        Some(Command::Completions(ref c)) => completions(c),
+        Some(Command::Export(ref e)) => export(args, e),
+        Some(Command::Edit(ref e)) => edit(args, e),
+        Some(Command::Stats(ref s)) => stats(args, s),
+        Some(Command::Du) => du(args),
+        Some(Command::Clean(ref c)) => clean(args, c),
+        Some(Command::Suggest(ref s)) => suggest(args, s),
+        Some(Command::Lint(ref l)) => lint(args, l),
+        Some(Command::Unused(ref u)) => unused(args, u),
+        Some(Command::Bench(ref b)) => bench(args, b),
+        Some(Command::Global(ref g)) => global(args, g),
+        Some(Command::List(ref l)) => list(args, l),
+        Some(Command::IgnoreDelete(ref i)) => ignore_delete(args, i),
+        Some(Command::Remove(ref r)) => remove(args, r),
+        Some(Command::Diff(ref d)) => diff(args, d),
+        Some(Command::Migrate(ref m)) => migrate(args, m),
+        Some(Command::Test(ref t)) => test(args, t),
+        Some(Command::Blame(ref b)) => blame(args, b),
+        Some(Command::Tidy(ref t)) => tidy(args, t),
+        Some(Command::Assemble) => assemble(args),
+        Some(Command::Complete(ref c)) => complete(args, c),
+        Some(Command::CopyFrom(ref c)) => copy_from(args, c),
+        None if args.all_folders => go_all_folders(args),
+        None => add(args),
+    }
+}
+
+/// Runs `go`, printing the report with --json
+fn add(args: &Args) -> Result<()> {
+    let mut report = Report::default();
+    let res = go(args, &mut report);
+    if args.json {
+        if let Err(ref e) = res {
+            report.errors.push(format!("{e:#}"));
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report is serializable")
+        );
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pattern: &str, prefix: &str) -> Result<String> {
+        let prefix = PathBuf::from(prefix);
+        process_patterns(
+            &[pattern.to_string()],
+            Some(&prefix),
+            syntax::Modifiers::default(),
+        )
+    }
+
+    fn folder() -> backend::Memory {
+        backend::Memory::with_files(&[
+            (
+                "/f/.stignore",
+                "a\n#include common.stignore\n#include missing.stignore\n",
+            ),
+            (
+                "/f/common.stignore",
+                "// shared\n/b\n#include sub/.stignore_sync\n",
+            ),
+            ("/f/sub/.stignore_sync", "!c\nc\n"),
+            ("/f/orphan.stignore", "d\n"),
+        ])
+    }
+
+    #[test]
+    fn ignore_files_follow_includes_in_the_backend() {
+        let files = ignore_files(&folder(), Path::new("/f"));
+        assert_eq!(
+            files,
+            [
+                "/f/.stignore",
+                "/f/common.stignore",
+                "/f/sub/.stignore_sync"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn reachability_is_checked_in_the_backend() {
+        let backend = folder();
+        let root = Path::new("/f");
+        assert!(is_reachable(
+            &backend,
+            root,
+            Path::new("/f/sub/.stignore_sync")
+        ));
+        assert!(is_reachable(
+            &backend,
+            root,
+            Path::new("/f/missing.stignore")
+        ));
+        assert!(!is_reachable(
+            &backend,
+            root,
+            Path::new("/f/orphan.stignore")
+        ));
+    }
+
+    #[test]
+    fn matcher_loads_from_the_backend() {
+        let backend = folder();
+        assert!(matcher::Matcher::load(&backend, Path::new("/f")).is_err());
+        backend
+            .write(Path::new("/f/missing.stignore"), b"")
+            .unwrap();
+        let matcher = matcher::Matcher::load(&backend, Path::new("/f")).unwrap();
+        let lines: Vec<_> = matcher.rules().iter().map(|r| r.line.as_str()).collect();
+        assert_eq!(lines, ["a", "/b", "!c", "c"]);
+        assert!(matcher.is_ignored("x/a"));
+        assert!(!matcher.is_ignored("c"));
+    }
+
+    #[test]
+    fn matcher_refuses_include_loops() {
+        let backend = backend::Memory::with_files(&[
+            ("/f/.stignore", "#include x.stignore\n"),
+            ("/f/x.stignore", "#include ./.stignore\n"),
+        ]);
+        assert!(matcher::Matcher::load(&backend, Path::new("/f")).is_err());
+    }
+
+    #[test]
+    fn dangling_includes_are_found_in_the_backend() {
+        let backend = folder();
+        let files = ignore_files(&backend, Path::new("/f"));
+        let dangling = includes::dangling(&backend, &files).unwrap();
+        assert_eq!(dangling.len(), 1);
+        assert_eq!(dangling[0].text, "#include missing.stignore");
+        assert!(corrupt::read(&backend, Path::new("/f/missing.stignore")).is_err());
+    }
+
+    #[test]
+    fn policy_is_read_from_the_backend() {
+        let backend = folder();
+        let root = Path::new("/f");
+        let stignore = root.join(".stignore");
+        backend
+            .write(
+                &root.join(policy::FILE),
+                b"forbidden = [\"e\"]\nrequired-sections = [\"// shared\"]\n",
+            )
+            .unwrap();
+        let old = corrupt::read(&backend, &stignore).unwrap();
+        // the required section is kept by common.stignore
+        assert!(enforce_policy(&backend, root, &stignore, &old, "f\n").is_ok());
+        assert!(enforce_policy(&backend, root, &stignore, &old, "e\n").is_err());
+    }
+
+    #[test]
+    fn policy_sees_patterns_where_go_puts_them() {
+        let backend = folder();
+        let root = Path::new("/f");
+        let file = root.join("common.stignore");
+        backend
+            .write(
+                &root.join(policy::FILE),
+                b"required-sections = [\"// shared\"]\n",
+            )
+            .unwrap();
+        let mut plan = Plan {
+            file: file.clone(),
+            old: "// shared\n/b\n".to_string(),
+            patterns: "/z\n".to_string(),
+            insertion: None,
+        };
+        let args =
+            |flags: &[&str]| Args::parse_from(["stignore"].iter().chain(flags).chain(&["z"]));
+        let (appended, rewritten) = planned_contents(&args(&[]), &plan);
+        assert_eq!(
+            (appended.as_str(), rewritten),
+            ("// shared\n/b\n/z\n", false)
+        );
+        let (sectioned, rewritten) = planned_contents(&args(&["--section", "z"]), &plan);
+        assert!(rewritten && sectioned.contains("// --- z ---\n/z"));
+        assert!(enforce_policy(&backend, root, &file, &plan.old, &sectioned).is_ok());
+
+        plan.insertion = insertion_line(&plan.old, false);
+        let (prepended, _) = planned_contents(&args(&["--prepend"]), &plan);
+        assert_eq!(prepended, "/z\n// shared\n/b\n");
+        assert!(enforce_policy(&backend, root, &file, &plan.old, &prepended).is_ok());
+        assert!(enforce_policy(&backend, root, &file, &plan.old, "/z\n/b\n").is_err());
+    }
+
+    #[test]
+    fn migration_restores_the_first_file_if_the_second_fails() {
+        let dir = std::env::temp_dir().join(format!("stignore-migrate-{}", std::process::id()));
+        let (first, second) = (dir.join(".stignore_sync"), dir.join(".stignore"));
+        // a directory in place of .stignore can't be written
+        std::fs::create_dir_all(&second).unwrap();
+        let backend = backend::Local { fsync: false };
+        std::fs::write(&first, "old\n").unwrap();
+        let pair = |old| rewrite_both(&backend, (&first, old, "new\n"), (&second, "x\n"));
+        assert!(pair(Some("old\n")).is_err());
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "old\n");
+        assert!(pair(None).is_err());
+        assert!(!first.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failures_map_to_exit_codes() {
+        let of = |failure| Status::of(&anyhow::Error::new(failure));
+        assert_eq!(of(Failure::NotInFolder), Status::NotInFolder);
+        assert_eq!(
+            of(Failure::InvalidPatterns(Vec::new())),
+            Status::InvalidPattern
+        );
+        assert_eq!(of(Failure::NothingToDo), Status::NothingToDo);
+        assert_eq!(of(Failure::ReadOnly(PathBuf::new())), Status::Io);
+        assert_eq!(of(Failure::Aborted), Status::Aborted);
+        // the reason is found under added context
+        let err = anyhow::Error::new(Failure::NotInteractive("?".to_string())).context("Can't add");
+        assert_eq!(Status::of(&err), Status::Aborted);
+        let err = anyhow::Error::new(std::io::Error::other("disk")).context("Can't write");
+        assert_eq!(Status::of(&err), Status::Io);
+        assert_eq!(Status::of(&anyhow::anyhow!("other")), Status::Error);
+    }
+
+    #[test]
+    fn routes_split_patterns_between_files() {
+        let backend = memory_backend();
+        let root = Path::new("/stignore-test-routes");
+        for (file, contents) in [
+            (".stignore", "#include python.stignore\n"),
+            ("python.stignore", ""),
+        ] {
+            backend
+                .write(&root.join(file), contents.as_bytes())
+                .unwrap();
+        }
+        let mut args = Args::parse_from(["stignore", "--backend", "memory", "x"]);
+        args.routes = vec![config::Route {
+            pattern: "*.pyc".to_string(),
+            file: PathBuf::from("python.stignore"),
+        }];
+        let patterns = "// build\n/a.pyc\n/out\n__pycache__/x.pyc\n".replace('\n', LINE_ENDING);
+        let targets = route_patterns(&args, root, &patterns, &mut Report::default()).unwrap();
+        let targets: Vec<_> = targets
+            .iter()
+            .map(|(file, patterns)| (file.to_str().unwrap(), patterns.replace(LINE_ENDING, "\n")))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                (
+                    "/stignore-test-routes/python.stignore",
+                    "// build\n/a.pyc\n__pycache__/x.pyc\n".to_string()
+                ),
+                (
+                    "/stignore-test-routes/.stignore",
+                    "// build\n/out\n".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn fast_append_only_appends() {
+        let dir = std::env::temp_dir().join(format!("stignore-fast-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join(".stfolder")).unwrap();
+        let stignore = dir.join(".stignore");
+        std::fs::write(&stignore, "/a\n").unwrap();
+        let root = dir.to_str().unwrap();
+        let args = Args::parse_from([
+            "stignore",
+            "--root",
+            root,
+            "--backend",
+            "memory",
+            "--fast-append",
+            "--silent",
+            "b",
+        ]);
+        go(&args, &mut Report::default()).unwrap();
+        let contents = args.backend().read(&stignore).unwrap().unwrap();
+        let contents = String::from_utf8(contents).unwrap();
+        assert_eq!(contents.replace(LINE_ENDING, "\n"), "/a\n/b\n");
+
+        // the policy can't be checked without reading the files
+        std::fs::write(dir.join(policy::FILE), "").unwrap();
+        assert!(go(&args, &mut Report::default()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pages_are_cut_from_the_listing() {
+        let items = [1, 2, 3, 4, 5];
+        let page = |limit, offset| PageArgs { limit, offset }.page(&items);
+        assert_eq!(page(None, 0), (&items[..], 0));
+        assert_eq!(page(Some(2), 0), (&items[..2], 3));
+        assert_eq!(page(Some(2), 4), (&items[4..], 0));
+        assert_eq!(page(None, 7), (&items[5..], 0));
+        assert_eq!(page(Some(usize::MAX), 1), (&items[1..], 0));
+    }
+
+    #[test]
+    fn parent_dirs_are_resolved_against_the_prefix() {
+        assert_eq!(
+            process("../cache/**", "/sub/dir").unwrap().trim(),
+            "/sub/cache/**"
+        );
+        assert_eq!(
+            process("./build/../cache", "/sub").unwrap().trim(),
+            "/sub/cache"
+        );
+    }
+
+    #[test]
+    fn trailing_slash_with_prefix() {
+        assert_eq!(process("./build/", "/sub").unwrap().trim(), "/sub/build/");
+    }
+
+    #[test]
+    fn parent_dirs_can_reach_the_root() {
+        assert_eq!(process("../x", "/sub").unwrap().trim(), "/x");
+    }
+
+    #[test]
+    fn parent_dirs_cant_escape_the_root() {
+        let err = process("../../x", "/sub").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Failure>(),
+            Some(Failure::InvalidPatterns(_))
+        ));
+        assert!(process("../x", "/").is_err());
+    }
+
+    #[test]
+    fn parent_dirs_without_prefix() {
+        let process = |pattern: &str| {
+            process_patterns(&[pattern.to_string()], None, syntax::Modifiers::default())
+        };
+        assert_eq!(process("./build/../cache/**").unwrap().trim(), "/cache/**");
+        assert!(process("/../x").is_err());
+    }
 }
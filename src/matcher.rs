@@ -0,0 +1,561 @@
+//! Matching of paths against ignore patterns, following syncthing's
+//! (https://docs.syncthing.net/users/ignoring) semantics.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use regex::{Regex, RegexBuilder};
+
+use crate::{backend::Backend, corrupt};
+
+/// Files that syncthing never syncs, regardless of patterns
+const INTERNAL: [&str; 3] = [".stfolder", ".stignore", ".stversions"];
+
+/// Is `path` (relative to the folder root) one of syncthing's internal files.
+/// `markers` are custom folder markers, used instead of `.stfolder`
+pub fn is_internal(path: &str, markers: &[String]) -> bool {
+    let first = path.split('/').next().unwrap_or(path);
+    INTERNAL.contains(&first) || markers.iter().any(|m| m == first)
+}
+
+/// Single pattern of an ignore file
+pub struct Rule {
+    /// The pattern as written in the ignore file
+    pub line: String,
+    pub negated: bool,
+    /// Ignore file and 1-based line number the rule was loaded from
+    pub location: Option<(PathBuf, usize)>,
+    pub case_insensitive: bool,
+    pub deletable: bool,
+    /// The pattern without modifiers
+    pattern: String,
+    /// Globs the pattern expands to, relative to the folder root
+    globs: Vec<String>,
+    regex: Regex,
+}
+
+impl Rule {
+    /// Parses a pattern line (without `#include`s and comments)
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut pattern = line.trim();
+        let (mut negated, mut case_insensitive, mut deletable) = (false, false, false);
+        loop {
+            if let (false, Some(rest)) = (negated, pattern.strip_prefix('!')) {
+                negated = true;
+                pattern = rest;
+            } else if let (false, Some(rest)) = (case_insensitive, pattern.strip_prefix("(?i)")) {
+                case_insensitive = true;
+                pattern = rest;
+            } else if let (false, Some(rest)) = (deletable, pattern.strip_prefix("(?d)")) {
+                deletable = true;
+                pattern = rest;
+            } else {
+                break;
+            }
+        }
+        if pattern.is_empty() {
+            bail!("Missing pattern in {line:?}");
+        }
+        let pattern = if cfg!(windows) {
+            pattern.replace('\\', "/")
+        } else {
+            pattern.to_string()
+        };
+
+        // the same expansion syncthing does: pattern matching a directory
+        // also matches its contents, unrooted patterns match at any depth
+        let with_contents = if pattern.ends_with("/**") {
+            vec![pattern.clone()]
+        } else if pattern.ends_with('/') {
+            vec![format!("{pattern}**")]
+        } else {
+            vec![pattern.clone(), format!("{pattern}/**")]
+        };
+        let mut globs = Vec::new();
+        for glob in with_contents {
+            if let Some(rooted) = glob.strip_prefix('/') {
+                globs.push(rooted.to_string());
+            } else if let Some(unrooted) = glob.strip_prefix("**/") {
+                globs.push(unrooted.to_string());
+                globs.push(glob);
+            } else {
+                globs.push(format!("**/{glob}"));
+                globs.push(glob);
+            }
+        }
+
+        let alternatives = globs
+            .iter()
+            .map(|g| glob_to_regex(g))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid pattern {line:?}"))?;
+        let regex = RegexBuilder::new(&format!("^(?:{})$", alternatives.join("|")))
+            .case_insensitive(case_insensitive)
+            .build()
+            .with_context(|| format!("Invalid pattern {line:?}"))?;
+        Ok(Self {
+            line: line.trim().to_string(),
+            negated,
+            location: None,
+            case_insensitive,
+            deletable,
+            pattern,
+            globs,
+            regex,
+        })
+    }
+
+    /// Does the rule match `path` (relative to the folder root, `/`-separated)
+    pub fn matches(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+
+    /// Description of how the rule is compiled: modifiers, anchoring,
+    /// glob tokens and the resulting regex, one item per line
+    pub fn explain(&self) -> Vec<String> {
+        let mut lines = vec![format!("pattern: {}", self.pattern)];
+        if self.negated {
+            lines.push("negated (!): matching paths are not ignored".to_string());
+        }
+        if self.deletable {
+            lines.push(
+                "deletable ((?d)): doesn't prevent deletion of directories containing it"
+                    .to_string(),
+            );
+        }
+        lines.push(if self.case_insensitive {
+            "case folding applied ((?i))".to_string()
+        } else {
+            "case-sensitive".to_string()
+        });
+        lines.push(if self.pattern.starts_with('/') {
+            "anchored to the folder root".to_string()
+        } else {
+            "floating: matches at any depth".to_string()
+        });
+        lines.push("tokens:".to_string());
+        match tokenize(&self.pattern) {
+            Ok(tokens) => {
+                let width = tokens.iter().map(|t| t.text.len()).max().unwrap_or(0);
+                lines.extend(
+                    tokens
+                        .iter()
+                        .map(|t| format!("  {:width$}  {}", t.text, t.meaning)),
+                );
+            }
+            Err(e) => lines.push(format!("  {e}")),
+        }
+        lines.push("expands to:".to_string());
+        lines.extend(self.globs.iter().map(|g| {
+            if g.ends_with("/**") && !self.pattern.ends_with("/**") {
+                format!("  {g}  (contents of a matching directory)")
+            } else {
+                format!("  {g}")
+            }
+        }));
+        lines.push(format!("regex: {}", self.regex.as_str()));
+        lines
+    }
+}
+
+/// Meaningful piece of a glob
+struct Token {
+    /// Text of the glob
+    text: String,
+    /// Equivalent regex
+    regex: String,
+    meaning: &'static str,
+}
+
+/// Splits a glob into tokens, consecutive literal characters are merged
+fn tokenize(glob: &str) -> Result<Vec<Token>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut chars = glob.chars().peekable();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        let (text, regex, meaning) = match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                (
+                    "**".to_string(),
+                    ".*".to_string(),
+                    "any characters, including /",
+                )
+            }
+            '*' => (
+                "*".to_string(),
+                "[^/]*".to_string(),
+                "any characters except /",
+            ),
+            '?' => (
+                "?".to_string(),
+                "[^/]".to_string(),
+                "one character except /",
+            ),
+            '\\' if !cfg!(windows) => match chars.next() {
+                Some(escaped) => (
+                    escaped.to_string(),
+                    regex::escape(&escaped.to_string()),
+                    "literal",
+                ),
+                None => bail!("Trailing \\"),
+            },
+            '[' => {
+                let mut text = String::from("[");
+                let mut class = String::new();
+                let mut closed = false;
+                let negated = chars.peek() == Some(&'!');
+                if negated {
+                    chars.next();
+                    text.push('!');
+                    class.push_str("^/");
+                }
+                for c in chars.by_ref() {
+                    text.push(c);
+                    match c {
+                        ']' => {
+                            closed = true;
+                            break;
+                        }
+                        '\\' | '[' | '^' | '&' | '~' => {
+                            class.push('\\');
+                            class.push(c);
+                        }
+                        _ => class.push(c),
+                    }
+                }
+                if !closed {
+                    bail!("Unclosed [");
+                }
+                (
+                    text,
+                    format!("[{class}]"),
+                    if negated {
+                        "one character except these"
+                    } else {
+                        "one of these characters"
+                    },
+                )
+            }
+            '{' => {
+                depth += 1;
+                ("{".to_string(), "(?:".to_string(), "start of alternatives")
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                ("}".to_string(), ")".to_string(), "end of alternatives")
+            }
+            ',' if depth > 0 => (",".to_string(), "|".to_string(), "or"),
+            _ => (c.to_string(), regex::escape(&c.to_string()), "literal"),
+        };
+        match tokens.last_mut() {
+            Some(last) if last.meaning == "literal" && meaning == "literal" => {
+                last.text.push_str(&text);
+                last.regex.push_str(&regex);
+            }
+            _ => tokens.push(Token {
+                text,
+                regex,
+                meaning,
+            }),
+        }
+    }
+    if depth > 0 {
+        bail!("Unclosed {{");
+    }
+    Ok(tokens)
+}
+
+/// Translates a glob into an unanchored regex
+fn glob_to_regex(glob: &str) -> Result<String> {
+    Ok(tokenize(glob)?.into_iter().map(|t| t.regex).collect())
+}
+
+/// Patterns of a syncthing folder, with `#include`s resolved
+#[derive(Default)]
+pub struct Matcher {
+    rules: Vec<Rule>,
+    /// Custom folder markers, internal files just like `.stfolder`
+    markers: Vec<String>,
+}
+
+impl Matcher {
+    /// Loads patterns from `.stignore` in the folder `root`
+    pub fn load(backend: &dyn Backend, root: &Path) -> Result<Self> {
+        let mut matcher = Self::default();
+        let stignore = root.join(".stignore");
+        if backend.exists(&stignore) {
+            matcher.load_file(backend, &stignore, &mut Vec::new())?;
+        }
+        Ok(matcher)
+    }
+
+    /// Loads patterns from the ignore file `path`
+    pub fn from_file(backend: &dyn Backend, path: &Path) -> Result<Self> {
+        let mut matcher = Self::default();
+        matcher.load_file(backend, path, &mut Vec::new())?;
+        Ok(matcher)
+    }
+
+    /// The matcher with custom folder `markers` treated as internal files
+    pub fn with_markers(mut self, markers: &[String]) -> Self {
+        self.markers = markers.to_vec();
+        self
+    }
+
+    /// Is `path` (relative to the folder root) one of syncthing's internal files
+    pub fn is_internal(&self, path: &str) -> bool {
+        is_internal(path, &self.markers)
+    }
+
+    fn load_file(
+        &mut self,
+        backend: &dyn Backend,
+        path: &Path,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let canonical = backend
+            .canonicalize(path)
+            .with_context(|| format!("Can't read {}", path.display()))?;
+        if stack.contains(&canonical) {
+            bail!("{} includes itself", path.display());
+        }
+        let contents = corrupt::read(backend, path)?;
+        debug!("Loading patterns from {}", path.display());
+        stack.push(canonical);
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(include) = line.strip_prefix("#include") {
+                let include = path.parent().unwrap().join(include.trim());
+                info!(
+                    "{}:{} includes {}",
+                    path.display(),
+                    n + 1,
+                    include.display()
+                );
+                self.load_file(backend, &include, stack)
+                    .with_context(|| format!("Include at {}:{}", path.display(), n + 1))?;
+                continue;
+            }
+            let mut rule =
+                Rule::parse(line).with_context(|| format!("{}:{}", path.display(), n + 1))?;
+            rule.location = Some((path.to_path_buf(), n + 1));
+            self.rules.push(rule);
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Is `path` (relative to the folder root, `/`-separated) ignored
+    pub fn is_ignored(&self, path: &str) -> bool {
+        if self.is_internal(path) {
+            return true;
+        }
+        self.rule_for(path).is_some_and(|r| !r.negated)
+    }
+
+    /// First rule matching `path`, the one that decides whether it's ignored
+    pub fn rule_for(&self, path: &str) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.matches(path))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Rules that match none of the files and directories below `root`, with
+    /// paths that couldn't be read. Ignored directories are walked too.
+    ///
+    /// Each path is only checked against rules that haven't matched anything yet
+    pub fn unused(&self, root: &Path) -> (Vec<&Rule>, Vec<PathBuf>) {
+        let mut unused: Vec<_> = self.rules.iter().collect();
+        let mut unreadable = Vec::new();
+        self.walk_all(root, "", &mut unused, &mut unreadable);
+        (unused, unreadable)
+    }
+
+    fn walk_all(
+        &self,
+        dir: &Path,
+        rel: &str,
+        unused: &mut Vec<&Rule>,
+        unreadable: &mut Vec<PathBuf>,
+    ) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                unreadable.push(dir.to_path_buf());
+                return;
+            }
+        };
+        for entry in entries {
+            if unused.is_empty() {
+                return;
+            }
+            let Ok(entry) = entry else {
+                unreadable.push(dir.to_path_buf());
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = if rel.is_empty() {
+                name
+            } else {
+                format!("{rel}/{name}")
+            };
+            if self.is_internal(&path) {
+                continue;
+            }
+            unused.retain(|r| !r.matches(&path));
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                self.walk_all(&entry.path(), &path, unused, unreadable);
+            }
+        }
+    }
+
+    /// Whether some patterns can un-ignore paths inside of ignored directories
+    pub fn has_negations(&self) -> bool {
+        self.rules.iter().any(|r| r.negated)
+    }
+}
+
+/// File or ignored directory found by [`walk`]
+pub struct Entry {
+    /// Relative to the folder root, `/`-separated
+    pub path: String,
+    pub dir: bool,
+    pub ignored: bool,
+    /// Total size of files, including directory contents
+    pub size: u64,
+    /// Number of files, including directory contents
+    pub files: u64,
+}
+
+/// Walks the folder `root`, calling `f` for each file.
+///
+/// Ignored directories are reported as a single entry, unless some negated
+/// pattern could match their contents.
+/// Syncthing's internal files are skipped. Returns paths that couldn't be read.
+pub fn walk(root: &Path, matcher: &Matcher, f: &mut dyn FnMut(Entry)) -> Vec<PathBuf> {
+    let mut unreadable = Vec::new();
+    walk_dir(root, "", matcher, f, &mut unreadable);
+    unreadable
+}
+
+fn walk_dir(
+    dir: &Path,
+    rel: &str,
+    matcher: &Matcher,
+    f: &mut dyn FnMut(Entry),
+    unreadable: &mut Vec<PathBuf>,
+) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            unreadable.push(dir.to_path_buf());
+            return;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => {
+                unreadable.push(dir.to_path_buf());
+                continue;
+            }
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if rel.is_empty() {
+            name
+        } else {
+            format!("{rel}/{name}")
+        };
+        if matcher.is_internal(&path) {
+            continue;
+        }
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => {
+                unreadable.push(entry.path());
+                continue;
+            }
+        };
+        let ignored = matcher.is_ignored(&path);
+        if meta.is_dir() {
+            if ignored && !matcher.has_negations() {
+                let (size, files) = dir_size(&entry.path(), unreadable);
+                f(Entry {
+                    path,
+                    dir: true,
+                    ignored,
+                    size,
+                    files,
+                });
+            } else {
+                walk_dir(&entry.path(), &path, matcher, f, unreadable);
+            }
+        } else {
+            f(Entry {
+                path,
+                dir: false,
+                ignored,
+                size: meta.len(),
+                files: 1,
+            });
+        }
+    }
+}
+
+/// Total size and number of files in `dir`, adding paths that couldn't be
+/// read to `unreadable`
+pub fn dir_size(dir: &Path, unreadable: &mut Vec<PathBuf>) -> (u64, u64) {
+    let (mut size, mut files) = (0, 0);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => {
+            unreadable.push(dir.to_path_buf());
+            return (0, 0);
+        }
+    };
+    for entry in entries.flatten() {
+        match entry.metadata() {
+            Ok(meta) if meta.is_dir() => {
+                let (s, f) = dir_size(&entry.path(), unreadable);
+                size += s;
+                files += f;
+            }
+            Ok(meta) => {
+                size += meta.len();
+                files += 1;
+            }
+            Err(_) => unreadable.push(entry.path()),
+        }
+    }
+    (size, files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Memory;
+
+    #[test]
+    fn empty_directories_use_rules() {
+        let root = std::env::temp_dir().join(format!("stignore-unused-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        std::fs::create_dir_all(root.join(".stfolder")).unwrap();
+        std::fs::write(root.join("notes.txt"), "").unwrap();
+        let stignore = root.join(".stignore");
+        let patterns = "/build\n*.txt\n/missing\n.stfolder\n";
+        let backend = Memory::with_files(&[(stignore.to_str().unwrap(), patterns)]);
+        let matcher = Matcher::from_file(&backend, &stignore).unwrap();
+        let (unused, unreadable) = matcher.unused(&root);
+        let unused: Vec<_> = unused.iter().map(|r| r.line.as_str()).collect();
+        assert_eq!(unused, ["/missing", ".stfolder"]);
+        assert!(unreadable.is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Owners and last access times of files, to break suggestions down on
+//! servers shared by several users.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long ago a file or directory was last accessed, oldest first
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Age {
+    OverAYear,
+    OverAMonth,
+    Recent,
+    /// Access times can't be read, e.g. of an empty directory
+    Unknown,
+}
+
+impl Age {
+    pub fn of(accessed: Option<SystemTime>, now: SystemTime) -> Self {
+        match accessed.map(|t| now.duration_since(t).unwrap_or_default()) {
+            None => Age::Unknown,
+            Some(age) if age >= 365 * DAY => Age::OverAYear,
+            Some(age) if age >= 30 * DAY => Age::OverAMonth,
+            Some(_) => Age::Recent,
+        }
+    }
+}
+
+impl std::fmt::Display for Age {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Age::OverAYear => "not accessed for over a year",
+            Age::OverAMonth => "not accessed for over a month",
+            Age::Recent => "accessed in the last month",
+            Age::Unknown => "access time unknown",
+        })
+    }
+}
+
+/// `1 day ago`, `3 days ago`, `today`
+pub fn days_ago(accessed: SystemTime, now: SystemTime) -> String {
+    let days = now.duration_since(accessed).unwrap_or_default().as_secs() / DAY.as_secs();
+    match days {
+        0 => "today".to_string(),
+        1 => "1 day ago".to_string(),
+        days => format!("{days} days ago"),
+    }
+}
+
+/// Latest access time of the file at `path` or of any file inside of the
+/// directory, symlinks aren't followed
+pub fn last_access(path: &Path) -> Option<SystemTime> {
+    let meta = std::fs::symlink_metadata(path).ok()?;
+    if !meta.is_dir() {
+        return meta.accessed().ok();
+    }
+    // listing a directory updates its own access time, only files count
+    std::fs::read_dir(path)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| last_access(&entry.path()))
+        .max()
+}
+
+/// Name of the user that owns the file at `path`, `uid N` if the user has no
+/// name and `None` if the owner can't be found out
+#[cfg(unix)]
+pub fn owner(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = std::fs::symlink_metadata(path).ok()?.uid();
+    Some(user_name(uid).unwrap_or_else(|| format!("uid {uid}")))
+}
+
+#[cfg(not(unix))]
+pub fn owner(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn user_name(uid: u32) -> Option<String> {
+    use std::ffi::CStr;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0u8; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: buffers are valid for their lengths, the name is only read
+    // while `buf` is alive
+    let res = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if res != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
@@ -0,0 +1,92 @@
+//! Restrictions on changes of ignore files, set by the folder's admin.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::{backend::Backend, syntax};
+
+/// Name of the policy file, placed next to `.stfolder`
+pub const FILE: &str = ".stignore-policy.toml";
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Patterns that can't be added, compared without `(?d)` and `(?i)`
+    pub forbidden: Vec<String>,
+    /// Lines (usually `//` comments) that the ignore files must keep
+    pub required_sections: Vec<String>,
+    /// Can patterns with `(?d)` be added
+    pub allow_deletable: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            forbidden: Vec::new(),
+            required_sections: Vec::new(),
+            allow_deletable: true,
+        }
+    }
+}
+
+/// `pattern` without `(?d)`, `(?i)` and a trailing `/`, for comparisons
+fn normalize(pattern: &str) -> String {
+    let (prefix, pattern) = syntax::split_modifiers(pattern.trim());
+    let negation = if prefix.contains('!') { "!" } else { "" };
+    let pattern = match pattern.trim_end_matches('/') {
+        "" => pattern,
+        trimmed => trimmed,
+    };
+    format!("{negation}{pattern}")
+}
+
+impl Policy {
+    /// Policy of the folder `root`, `None` if it doesn't have one
+    pub fn load(backend: &dyn Backend, root: &Path) -> Result<Option<Self>> {
+        let path = root.join(FILE);
+        let contents = match backend.read(&path) {
+            Ok(Some(contents)) => String::from_utf8(contents)
+                .with_context(|| format!("{} isn't valid UTF-8", path.display()))?,
+            Ok(None) => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Can't read {}", path.display())),
+        };
+        debug!("Loaded policy {}", path.display());
+        toml::from_str(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid policy {}", path.display()))
+    }
+
+    /// Violations of changing an ignore file from `old` to `new` contents.
+    /// `others` are contents of the rest of the folder's ignore files
+    pub fn check(&self, old: &str, new: &str, others: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+        let old: Vec<_> = old.lines().map(str::trim).collect();
+        let forbidden: Vec<_> = self.forbidden.iter().map(|f| normalize(f)).collect();
+        for line in new.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with("//") || line.starts_with("#include") {
+                continue;
+            }
+            if old.contains(&line) {
+                continue;
+            }
+            let (prefix, _) = syntax::split_modifiers(line);
+            if !self.allow_deletable && prefix.contains("(?d)") {
+                violations.push(format!("{line}: (?d) patterns aren't allowed"));
+            }
+            if let Some(n) = forbidden.iter().position(|f| *f == normalize(line)) {
+                violations.push(format!("{line}: forbidden by {:?}", self.forbidden[n]));
+            }
+        }
+        for section in &self.required_sections {
+            let section = section.trim();
+            let present = |contents: &str| contents.lines().any(|l| l.trim() == section);
+            if !present(new) && !present(others) {
+                violations.push(format!("required section {section:?} is missing"));
+            }
+        }
+        violations
+    }
+}
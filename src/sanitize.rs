@@ -0,0 +1,121 @@
+//! Anonymization of ignore patterns, so ignore files can be shared without
+//! revealing private file names.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+
+/// Replaces names in patterns with placeholders.
+///
+/// Same name is always replaced with the same placeholder, modifiers,
+/// wildcards, directory structure and file extensions are preserved.
+pub struct Sanitizer {
+    names: HashMap<String, String>,
+    prefix_re: Regex,
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        Self {
+            names: HashMap::new(),
+            prefix_re: Regex::new(r"^((?:(?:\(\?[di]\)|!))*)(.*)$").unwrap(),
+        }
+    }
+
+    /// Sanitizes a single line of an ignore file
+    pub fn line(&mut self, line: &str) -> String {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return String::new();
+        }
+        if trimmed.starts_with("//") {
+            return "// comment".to_string();
+        }
+        if let Some(path) = trimmed.strip_prefix("#include") {
+            return format!("#include {}", self.glob(path.trim()));
+        }
+        let m = self.prefix_re.captures(trimmed).unwrap();
+        let glob = self.glob(&m[2]);
+        format!("{}{glob}", &m[1])
+    }
+
+    fn glob(&mut self, glob: &str) -> String {
+        let mut out = String::new();
+        let mut literal = String::new();
+        let mut chars = glob.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if !cfg!(windows) => {
+                    literal.push(c);
+                    literal.extend(chars.next());
+                }
+                '/' | '*' | '?' => {
+                    self.flush(&mut literal, &mut out);
+                    out.push(c);
+                }
+                '\\' => {
+                    // path separator on windows
+                    self.flush(&mut literal, &mut out);
+                    out.push(c);
+                }
+                '[' => {
+                    // character classes are short and rarely reveal anything
+                    self.flush(&mut literal, &mut out);
+                    out.push(c);
+                    for c in chars.by_ref() {
+                        out.push(c);
+                        if c == ']' {
+                            break;
+                        }
+                    }
+                }
+                '{' => {
+                    self.flush(&mut literal, &mut out);
+                    let mut depth = 1;
+                    let mut alternatives = vec![String::new()];
+                    for c in chars.by_ref() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            ',' if depth == 1 => {
+                                alternatives.push(String::new());
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                        alternatives.last_mut().unwrap().push(c);
+                    }
+                    let alternatives: Vec<_> = alternatives.iter().map(|a| self.glob(a)).collect();
+                    out.push('{');
+                    out.push_str(&alternatives.join(","));
+                    out.push('}');
+                }
+                _ => literal.push(c),
+            }
+        }
+        self.flush(&mut literal, &mut out);
+        out
+    }
+
+    /// Replaces the `literal` part of the name with a placeholder
+    fn flush(&mut self, literal: &mut String, out: &mut String) {
+        if literal.is_empty() {
+            return;
+        }
+        // keeps extensions and names of dotfiles (".git" is all extension)
+        let (name, ext) = literal.split_at(literal.rfind('.').unwrap_or(literal.len()));
+        if !name.is_empty() {
+            let next = self.names.len() + 1;
+            out.push_str(
+                self.names
+                    .entry(name.to_string())
+                    .or_insert_with(|| format!("name{next}")),
+            );
+        }
+        out.push_str(ext);
+        literal.clear();
+    }
+}
@@ -0,0 +1,158 @@
+//! Named sections of ignore files, started by `// --- name ---` comments.
+
+use std::ops::Range;
+
+use crate::tidy;
+
+/// Comment line that starts the section `name`
+pub fn header(name: &str) -> String {
+    format!("// --- {name} ---")
+}
+
+/// Name of the section started by `line`, if it's a section header
+pub fn name(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("// ---")?
+        .strip_suffix("---")
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+}
+
+/// Lines of section `name` in `all` without the header, the section is
+/// created at the end if it doesn't exist
+fn body(all: &mut Vec<String>, section: &str) -> Range<usize> {
+    match all.iter().position(|l| name(l) == Some(section)) {
+        Some(n) => {
+            let start = n + 1;
+            let end = all[start..]
+                .iter()
+                .position(|l| name(l).is_some())
+                .map_or(all.len(), |m| start + m);
+            start..end
+        }
+        None => {
+            if all.last().is_some_and(|l| !l.trim().is_empty()) {
+                all.push(String::new());
+            }
+            all.push(header(section));
+            all.len()..all.len()
+        }
+    }
+}
+
+/// Section `name` of `contents` without the header, empty if there's no such section
+pub fn lines<'a>(contents: &'a str, section: &str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .skip_while(|l| name(l) != Some(section))
+        .skip(1)
+        .take_while(|l| name(l).is_none())
+        .collect()
+}
+
+/// `contents` with the patterns of section `name` replaced by `lines`, keeping
+/// blank lines that separate it from the next section
+pub fn replace(contents: &str, section: &str, lines: &[&str]) -> String {
+    let ending = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut all: Vec<String> = contents.lines().map(String::from).collect();
+    let body = body(&mut all, section);
+    let gap = all[body.clone()]
+        .iter()
+        .rev()
+        .take_while(|l| l.trim().is_empty())
+        .count();
+    let new = lines
+        .iter()
+        .map(|l| l.to_string())
+        .chain(std::iter::repeat_n(String::new(), gap));
+    all.splice(body, new);
+    all.iter().map(|l| format!("{l}{ending}")).collect()
+}
+
+/// `contents` with `lines` added to the end of section `name`, which is
+/// created at the end of the file if it doesn't exist. With `sorted` patterns
+/// are inserted into their alphabetical positions within the section if possible
+pub fn insert(contents: &str, section: &str, lines: &[&str], sorted: bool) -> String {
+    let ending = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut all: Vec<String> = contents.lines().map(String::from).collect();
+    let body = body(&mut all, section);
+
+    let mut text: String = all[body.clone()]
+        .iter()
+        .map(|l| format!("{l}{ending}"))
+        .collect();
+    let kept = sorted.then(|| tidy::insert_sorted(&text, lines)).flatten();
+    text = match kept {
+        Some(sorted) => sorted,
+        None => {
+            // before blank lines that separate the section from the next one
+            let trimmed = text.trim_end().len();
+            let (body, gap) = text.split_at(trimmed);
+            let mut out = body.to_string();
+            if !out.is_empty() {
+                out.push_str(ending);
+            }
+            for line in lines {
+                out.push_str(line);
+                out.push_str(ending);
+            }
+            if gap.matches('\n').count() > 1 {
+                out.push_str(ending);
+            }
+            out
+        }
+    };
+
+    let mut out = String::new();
+    for line in &all[..body.start] {
+        out.push_str(line);
+        out.push_str(ending);
+    }
+    out.push_str(&text);
+    for line in &all[body.end..] {
+        out.push_str(line);
+        out.push_str(ending);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "/a\n\n// --- build ---\n/target\n\n// --- editor ---\n*.swp\n";
+
+    #[test]
+    fn patterns_go_to_the_end_of_their_section() {
+        assert_eq!(
+            insert(CONTENTS, "build", &["/dist"], false),
+            "/a\n\n// --- build ---\n/target\n/dist\n\n// --- editor ---\n*.swp\n"
+        );
+        assert_eq!(
+            insert(CONTENTS, "build", &["/out"], true),
+            "/a\n\n// --- build ---\n/out\n/target\n\n// --- editor ---\n*.swp\n"
+        );
+        assert_eq!(
+            insert("/a\n", "new", &["/b"], false),
+            "/a\n\n// --- new ---\n/b\n"
+        );
+    }
+
+    #[test]
+    fn sections_are_read_and_replaced_by_name() {
+        assert_eq!(lines(CONTENTS, "build"), ["/target", ""]);
+        assert_eq!(lines(CONTENTS, "missing"), Vec::<&str>::new());
+        assert_eq!(
+            replace(CONTENTS, "build", &["/dist"]),
+            "/a\n\n// --- build ---\n/dist\n\n// --- editor ---\n*.swp\n"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Statistics of ignored and synced data in a folder.
+
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    color::Palette,
+    matcher::{self, Matcher},
+};
+
+#[derive(Default)]
+pub struct Totals {
+    pub synced_files: u64,
+    pub synced_size: u64,
+    pub ignored_files: u64,
+    pub ignored_size: u64,
+    /// Biggest ignored files and directories, largest first
+    pub largest_ignored: Vec<Item>,
+    /// Biggest synced files, largest first
+    pub largest_synced: Vec<Item>,
+    /// Number and size of ignored files by top-level directory, files in the
+    /// folder root are under `""`
+    pub ignored_by_dir: BTreeMap<String, (u64, u64)>,
+}
+
+pub struct Item {
+    /// Relative to the folder root, `/`-separated
+    pub path: String,
+    pub dir: bool,
+    pub size: u64,
+}
+
+/// Keeps `n` largest of `items`
+fn keep_largest(items: &mut Vec<Item>, n: usize) {
+    items.sort_by_key(|i| std::cmp::Reverse(i.size));
+    items.truncate(n);
+}
+
+impl Totals {
+    /// Walks the folder `root`, printing a warning about unreadable paths.
+    ///
+    /// Up to `largest` biggest ignored and synced items are kept
+    pub fn collect(root: &Path, matcher: &Matcher, palette: &Palette, largest: usize) -> Self {
+        let mut totals = Self::default();
+        let unreadable = matcher::walk(root, matcher, &mut |entry| {
+            if entry.ignored {
+                let top = match entry.path.split_once('/') {
+                    Some((dir, _)) => dir,
+                    None if entry.dir => &entry.path,
+                    None => "",
+                };
+                let (files, size) = totals.ignored_by_dir.entry(top.to_string()).or_default();
+                *files += entry.files;
+                *size += entry.size;
+            }
+            let (files, size, items) = if entry.ignored {
+                (
+                    &mut totals.ignored_files,
+                    &mut totals.ignored_size,
+                    &mut totals.largest_ignored,
+                )
+            } else {
+                (
+                    &mut totals.synced_files,
+                    &mut totals.synced_size,
+                    &mut totals.largest_synced,
+                )
+            };
+            *files += entry.files;
+            *size += entry.size;
+            if largest > 0 {
+                items.push(Item {
+                    path: entry.path,
+                    dir: entry.dir,
+                    size: entry.size,
+                });
+                if items.len() > largest * 2 {
+                    keep_largest(items, largest);
+                }
+            }
+        });
+        keep_largest(&mut totals.largest_ignored, largest);
+        keep_largest(&mut totals.largest_synced, largest);
+        if !unreadable.is_empty() {
+            eprintln!(
+                "{} {} path{} couldn't be read and {} not counted",
+                palette.note("NOTE:"),
+                unreadable.len(),
+                if unreadable.len() > 1 { "s" } else { "" },
+                if unreadable.len() > 1 { "are" } else { "is" },
+            );
+        }
+        totals
+    }
+
+    /// Share of ignored data by size, in percent
+    pub fn ignored_percent(&self) -> f64 {
+        let total = self.synced_size + self.ignored_size;
+        if total == 0 {
+            return 0.0;
+        }
+        self.ignored_size as f64 * 100.0 / total as f64
+    }
+
+    /// shields.io endpoint badge (https://shields.io/badges/endpoint-badge)
+    pub fn badge(&self) -> serde_json::Value {
+        serde_json::json!({
+            "schemaVersion": 1,
+            "label": "ignored",
+            "message": format!(
+                "{:.0}% / {}",
+                self.ignored_percent(),
+                human_size(self.ignored_size)
+            ),
+            "color": "blue",
+        })
+    }
+}
+
+impl std::fmt::Display for Totals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Synced:  {} files, {}",
+            self.synced_files,
+            human_size(self.synced_size)
+        )?;
+        write!(
+            f,
+            "Ignored: {} files, {} ({:.0}%)",
+            self.ignored_files,
+            human_size(self.ignored_size),
+            self.ignored_percent()
+        )
+    }
+}
+
+/// Formats `bytes` with decimal units: `1.2 GB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["kB", "MB", "GB", "TB", "PB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64;
+    let mut unit = "";
+    for u in UNITS {
+        size /= 1000.0;
+        unit = u;
+        if size < 1000.0 {
+            break;
+        }
+    }
+    format!("{size:.1} {unit}")
+}
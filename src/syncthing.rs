@@ -0,0 +1,274 @@
+//! Syncthing's own configuration (config.xml) and REST API
+//! (https://docs.syncthing.net/dev/rest).
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Folder shared by syncthing
+pub struct Folder {
+    pub id: String,
+    pub label: String,
+    pub path: PathBuf,
+    /// Name of the file or directory in the root that marks the folder
+    pub marker: String,
+}
+
+/// Parts of config.xml that stignore needs
+pub struct Config {
+    pub path: PathBuf,
+    pub folders: Vec<Folder>,
+    /// REST API of the GUI, `None` if GUI is disabled
+    pub api: Option<Api>,
+}
+
+#[derive(Clone)]
+pub struct Api {
+    url: String,
+    key: String,
+}
+
+/// Parts of `/rest/db/status` of a folder
+#[derive(Deserialize, Debug)]
+#[serde(default, rename_all = "camelCase")]
+pub struct DbStatus {
+    /// `idle`, `scanning`, `syncing`...
+    pub state: String,
+    pub local_files: u64,
+    pub local_bytes: u64,
+    pub global_files: u64,
+    pub global_bytes: u64,
+}
+
+/// `/rest/db/ignores` of a folder
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct Ignores {
+    /// Lines of `.stignore`
+    pub ignore: Option<Vec<String>>,
+    /// Patterns syncthing matches files against, with `#include`s expanded
+    pub expanded: Option<Vec<String>>,
+}
+
+impl Default for DbStatus {
+    fn default() -> Self {
+        Self {
+            state: "unknown".to_string(),
+            local_files: 0,
+            local_bytes: 0,
+            global_files: 0,
+            global_bytes: 0,
+        }
+    }
+}
+
+/// Directories syncthing keeps its config.xml in, most recent locations first
+fn config_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(home) = std::env::var_os("STHOMEDIR").filter(|h| !h.is_empty()) {
+        dirs.push(PathBuf::from(home));
+    }
+    if cfg!(windows) {
+        if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local).join("Syncthing"));
+        }
+    } else if cfg!(target_os = "macos") {
+        if let Ok(home) = crate::home_dir() {
+            dirs.push(home.join("Library/Application Support/Syncthing"));
+        }
+    } else {
+        if let Ok(state) = crate::xdg_dir("XDG_STATE_HOME", ".local/state") {
+            dirs.push(state.join("syncthing"));
+        }
+        if let Ok(config) = crate::xdg_dir("XDG_CONFIG_HOME", ".config") {
+            dirs.push(config.join("syncthing"));
+        }
+    }
+    dirs
+}
+
+/// `~/path` as an absolute path
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), crate::home_dir()) {
+        (Some(rest), Ok(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Config {
+    /// Finds and reads config.xml of the local syncthing instance
+    pub fn load() -> Result<Self> {
+        let path = config_dirs()
+            .into_iter()
+            .map(|dir| dir.join("config.xml"))
+            .inspect(|p| debug!("Looking for {}", p.display()))
+            .find(|p| p.is_file())
+            .context("Can't find syncthing's config.xml, set $STHOMEDIR to its directory")?;
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Can't read {}", path.display()))?;
+        let config = Self::parse(path.clone(), &contents)
+            .with_context(|| format!("Invalid {}", path.display()))?;
+        info!("Loaded syncthing config {}", path.display());
+        Ok(config)
+    }
+
+    fn parse(path: PathBuf, contents: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(contents)?;
+        let root = doc.root_element();
+        let folders = root
+            .children()
+            .filter(|n| n.has_tag_name("folder"))
+            .map(|n| Folder {
+                id: n.attribute("id").unwrap_or_default().to_string(),
+                label: n.attribute("label").unwrap_or_default().to_string(),
+                path: expand_home(n.attribute("path").unwrap_or_default()),
+                marker: n
+                    .children()
+                    .find(|c| c.has_tag_name("markerName"))
+                    .and_then(|c| c.text())
+                    .map(str::trim)
+                    .filter(|m| !m.is_empty())
+                    .unwrap_or(".stfolder")
+                    .to_string(),
+            })
+            .collect();
+
+        let api = root
+            .children()
+            .find(|n| n.has_tag_name("gui"))
+            .filter(|gui| gui.attribute("enabled") != Some("false"))
+            .and_then(|gui| {
+                let text = |tag| {
+                    gui.children()
+                        .find(|n| n.has_tag_name(tag))
+                        .and_then(|n| n.text())
+                        .map(str::trim)
+                };
+                let address = text("address")?;
+                // GUI listening on all interfaces is reachable locally too
+                let address = address
+                    .strip_prefix("0.0.0.0:")
+                    .map(|port| format!("127.0.0.1:{port}"))
+                    .unwrap_or_else(|| address.to_string());
+                let scheme = if gui.attribute("tls") == Some("true") {
+                    "https"
+                } else {
+                    "http"
+                };
+                Some(Api {
+                    url: format!("{scheme}://{address}"),
+                    key: text("apikey")?.to_string(),
+                })
+            });
+        Ok(Self { path, folders, api })
+    }
+
+    /// Folder with the root `path`
+    pub fn folder(&self, path: &Path) -> Option<&Folder> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.folders
+            .iter()
+            .find(|f| f.path.canonicalize().unwrap_or_else(|_| f.path.clone()) == path)
+    }
+
+    pub fn api(&self) -> Result<&Api> {
+        let api = self
+            .api
+            .as_ref()
+            .with_context(|| format!("Syncthing GUI is disabled in {}", self.path.display()))?;
+        if api.url.starts_with("https:") {
+            bail!(
+                "Syncthing GUI at {} uses HTTPS, which isn't supported. \
+                Disable \"Use HTTPS for GUI\" in syncthing's settings",
+                api.url
+            );
+        }
+        Ok(api)
+    }
+}
+
+impl Api {
+    /// REST API of the syncthing instance with the GUI at `url`
+    pub fn remote(url: &str, key: &str) -> Result<Self> {
+        let url = url.trim_end_matches('/');
+        if url.starts_with("https:") {
+            bail!("Syncthing GUI at {url} uses HTTPS, which isn't supported");
+        }
+        Ok(Self {
+            url: url.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/rest/{path}", self.url)
+    }
+
+    /// Current configuration of the folder `id`
+    pub fn folder(&self, id: &str) -> Result<Value> {
+        let url = self.endpoint(&format!("config/folders/{id}"));
+        debug!("GET {url}");
+        ureq::get(&url)
+            .set("X-API-Key", &self.key)
+            .call()
+            .with_context(|| format!("Request to syncthing at {} failed", self.url))?
+            .into_json()
+            .context("Invalid response from syncthing")
+    }
+
+    /// Scan state and file counts of the folder `id`
+    pub fn db_status(&self, id: &str) -> Result<DbStatus> {
+        let url = self.endpoint("db/status");
+        debug!("GET {url}?folder={id}");
+        ureq::get(&url)
+            .query("folder", id)
+            .set("X-API-Key", &self.key)
+            .call()
+            .with_context(|| format!("Request to syncthing at {} failed", self.url))?
+            .into_json()
+            .context("Invalid response from syncthing")
+    }
+
+    /// Ignore patterns syncthing has loaded for the folder `id`
+    pub fn ignores(&self, id: &str) -> Result<Ignores> {
+        let url = self.endpoint("db/ignores");
+        debug!("GET {url}?folder={id}");
+        ureq::get(&url)
+            .query("folder", id)
+            .set("X-API-Key", &self.key)
+            .call()
+            .with_context(|| format!("Request to syncthing at {} failed", self.url))?
+            .into_json()
+            .context("Invalid response from syncthing")
+    }
+
+    /// Asks syncthing to rescan the folder `id` right away
+    pub fn scan(&self, id: &str) -> Result<()> {
+        let url = self.endpoint("db/scan");
+        debug!("POST {url}?folder={id}");
+        ureq::post(&url)
+            .query("folder", id)
+            .set("X-API-Key", &self.key)
+            .call()
+            .with_context(|| format!("Request to syncthing at {} failed", self.url))?;
+        Ok(())
+    }
+
+    /// Changes options of the folder `id` to `values`
+    pub fn patch_folder(&self, id: &str, values: Value) -> Result<()> {
+        let url = self.endpoint(&format!("config/folders/{id}"));
+        debug!("PATCH {url} {values}");
+        ureq::request("PATCH", &url)
+            .set("X-API-Key", &self.key)
+            .send_json(values)
+            .with_context(|| format!("Request to syncthing at {} failed", self.url))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,252 @@
+//! Syntax of single patterns: modifiers, normalization, escaping of literal
+//! names, and checks for common mistakes.
+
+use std::path::Path;
+
+use crate::backend::Backend;
+
+/// Splits pattern `line` into its `!`/`(?i)`/`(?d)` modifiers and the pattern itself
+pub fn split_modifiers(line: &str) -> (&str, &str) {
+    let mut pattern = line;
+    while let Some(rest) = pattern
+        .strip_prefix('!')
+        .or_else(|| pattern.strip_prefix("(?i)"))
+        .or_else(|| pattern.strip_prefix("(?d)"))
+    {
+        pattern = rest;
+    }
+    line.split_at(line.len() - pattern.len())
+}
+
+/// `(?d)`, `(?i)` and `!` prefixes of a pattern
+#[derive(Default, Copy, Clone, PartialEq, Debug)]
+pub struct Modifiers {
+    pub deletable: bool,
+    pub case_insensitive: bool,
+    pub negated: bool,
+}
+
+impl Modifiers {
+    /// Modifiers of the `prefix` returned by `split_modifiers`
+    pub fn parse(prefix: &str) -> Self {
+        Self {
+            deletable: prefix.contains("(?d)"),
+            case_insensitive: prefix.contains("(?i)"),
+            negated: prefix.contains('!'),
+        }
+    }
+
+    /// Modifiers present in either `self` or `other`
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            deletable: self.deletable || other.deletable,
+            case_insensitive: self.case_insensitive || other.case_insensitive,
+            negated: self.negated || other.negated,
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self == Self::default()
+    }
+}
+
+/// Modifiers in the `(?d)(?i)!` order, each one once
+impl std::fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.deletable {
+            f.write_str("(?d)")?;
+        }
+        if self.case_insensitive {
+            f.write_str("(?i)")?;
+        }
+        if self.negated {
+            f.write_str("!")?;
+        }
+        Ok(())
+    }
+}
+
+/// Modifiers of pattern `line` and the pattern itself. Repeated modifiers
+/// are merged; fails on combinations syncthing would misread
+pub fn parse_modifiers(line: &str) -> Result<(Modifiers, &str), &'static str> {
+    let (prefix, pattern) = split_modifiers(line);
+    let pattern = pattern.trim_start();
+    if pattern.starts_with("#include") {
+        return Err("#include can't have modifiers");
+    }
+    if pattern.starts_with("(?") {
+        return Err("unknown modifier, only (?i) and (?d) are supported");
+    }
+    if pattern.is_empty() {
+        return Err("modifiers without a pattern");
+    }
+    Ok((Modifiers::parse(prefix), pattern))
+}
+
+/// `pattern` (without modifiers) with `.` and `..` components resolved,
+/// `None` if it points above the folder root. A leading `./` anchors the
+/// pattern to the root, a trailing `/` is kept. Patterns without such
+/// components are returned as-is
+pub fn normalize(pattern: &str) -> Option<String> {
+    if !pattern.split('/').any(|p| p == "." || p == "..") {
+        return Some(pattern.to_string());
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    for part in pattern.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    let anchored = pattern.starts_with('/') || pattern.starts_with("./");
+    let trailing = pattern.ends_with('/') && !parts.is_empty();
+    Some(format!(
+        "{}{}{}",
+        if anchored { "/" } else { "" },
+        parts.join("/"),
+        if trailing { "/" } else { "" }
+    ))
+}
+
+/// Does `pattern` (relative to the current directory, without modifiers)
+/// match everything inside of it
+pub fn matches_everything(pattern: &str) -> bool {
+    let pattern = pattern.trim_start_matches("./").trim_matches('/');
+    matches!(pattern, "" | "." | "*" | "**" | "**/*" | "*/**")
+}
+
+/// Pattern matching the literal `path`: glob characters are escaped.
+///
+/// On Windows `\\` is a path separator and can't escape anything, so the
+/// path is used as-is
+pub fn escape(path: &str) -> String {
+    if cfg!(windows) {
+        return path.to_string();
+    }
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '{' | '}' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Pattern matching the file named `name` literally: glob characters are
+/// escaped, so are leading `!` and `#`, which would be taken for a negation
+/// or an `#include`
+pub fn literal(name: &str) -> String {
+    let escaped = escape(name);
+    if !cfg!(windows) && escaped.starts_with(['!', '#']) {
+        format!("\\{escaped}")
+    } else {
+        escaped
+    }
+}
+
+/// Does `pattern` match itself only, without any glob syntax
+pub fn is_literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '[', '{', '\\'])
+}
+
+/// Returns a description of the problem with the ignore file `line`, if any.
+///
+/// `dir` is the directory of the ignore file, used to resolve `#include`s.
+pub fn check_line(backend: &dyn Backend, line: &str, dir: &Path) -> Option<String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with("//") {
+        return None;
+    }
+    if let Some(rest) = line.strip_prefix("#include") {
+        let file = rest.trim();
+        if file.is_empty() {
+            return Some("#include without a file name".to_string());
+        }
+        if !rest.starts_with(char::is_whitespace) {
+            return Some("#include must be followed by a space".to_string());
+        }
+        if !backend.is_file(&dir.join(file)) {
+            return Some(format!("included file {file} doesn't exist"));
+        }
+        return None;
+    }
+    if line.starts_with('#') {
+        return Some("lines starting with # are patterns, use // for comments".to_string());
+    }
+
+    let pattern = match parse_modifiers(line) {
+        Ok((_, pattern)) => pattern,
+        Err(problem) => return Some(problem.to_string()),
+    };
+
+    let mut escaped = false;
+    let mut in_class = false;
+    let mut braces = 0i32;
+    for c in pattern.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if !cfg!(windows) => escaped = true,
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            '{' if !in_class => braces += 1,
+            '}' if !in_class => {
+                braces -= 1;
+                if braces < 0 {
+                    return Some("unmatched }".to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    if in_class {
+        return Some("unclosed [".to_string());
+    }
+    if braces > 0 {
+        return Some("unclosed {".to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_parent_dirs() {
+        assert_eq!(
+            normalize("./build/../cache/**").as_deref(),
+            Some("/cache/**")
+        );
+        assert_eq!(normalize("a/b/../c").as_deref(), Some("a/c"));
+    }
+
+    #[test]
+    fn normalize_anchors_leading_dot() {
+        assert_eq!(normalize("./build").as_deref(), Some("/build"));
+        assert_eq!(normalize("/./build").as_deref(), Some("/build"));
+    }
+
+    #[test]
+    fn normalize_keeps_trailing_slash() {
+        assert_eq!(normalize("./build/").as_deref(), Some("/build/"));
+        assert_eq!(normalize("a/../b/").as_deref(), Some("b/"));
+    }
+
+    #[test]
+    fn normalize_rejects_escaping_the_root() {
+        assert_eq!(normalize(".."), None);
+        assert_eq!(normalize("/../etc"), None);
+        assert_eq!(normalize("a/../../b"), None);
+    }
+
+    #[test]
+    fn normalize_keeps_plain_patterns() {
+        assert_eq!(normalize("*.tmp").as_deref(), Some("*.tmp"));
+        assert_eq!(normalize("/a/b/").as_deref(), Some("/a/b/"));
+        assert_eq!(normalize("a..b").as_deref(), Some("a..b"));
+    }
+}
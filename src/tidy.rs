@@ -0,0 +1,183 @@
+//! Cleaning up ignore files without changing what they ignore.
+
+use clap::ValueEnum;
+
+use crate::syntax;
+
+/// How patterns are sorted within groups delimited by comments, blank lines
+/// and `#include`s
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+pub enum SortOrder {
+    /// Keep the original order
+    None,
+    Alphabetical,
+    /// Alphabetical, ignoring case
+    CaseInsensitive,
+}
+
+/// Result of [`tidy`]
+#[derive(Default)]
+pub struct Tidied {
+    pub contents: String,
+    /// Patterns that were removed because they appear earlier in the file
+    pub duplicates: Vec<String>,
+    /// Groups whose order changed
+    pub sorted: usize,
+    /// Groups that weren't sorted, because they have negated patterns or mix
+    /// `(?d)` with regular ones, and the order of those matters
+    pub unsortable: usize,
+    pub blank_lines: usize,
+}
+
+fn is_pattern(line: &str) -> bool {
+    !line.is_empty() && !line.starts_with("//") && !line.starts_with('#')
+}
+
+/// What patterns are compared by, modifiers don't affect the order
+fn sort_key(line: &str, order: SortOrder) -> String {
+    let pattern = syntax::split_modifiers(line).1;
+    match order {
+        SortOrder::CaseInsensitive => pattern.to_lowercase(),
+        _ => pattern.to_string(),
+    }
+}
+
+/// Moves patterns of `group` to `lines`, sorting them in `order` if possible
+fn flush<'a>(
+    group: &mut Vec<&'a str>,
+    lines: &mut Vec<&'a str>,
+    order: SortOrder,
+    tidied: &mut Tidied,
+) {
+    if order != SortOrder::None && group.len() > 1 {
+        let modifiers: Vec<_> = group
+            .iter()
+            .map(|p| syntax::Modifiers::parse(syntax::split_modifiers(p).0))
+            .collect();
+        let negated = modifiers.iter().any(|m| m.negated);
+        let mixed = modifiers
+            .iter()
+            .any(|m| m.deletable != modifiers[0].deletable);
+        if negated || mixed {
+            tidied.unsortable += 1;
+        } else {
+            let original = group.clone();
+            group.sort_by_key(|p| sort_key(p, order));
+            if *group != original {
+                tidied.sorted += 1;
+            }
+        }
+    }
+    lines.append(group);
+}
+
+/// `contents` of an ignore file with duplicate patterns removed, groups of
+/// patterns sorted in `order` and runs of blank lines collapsed into one.
+/// Comments and `#include`s stay where they were
+pub fn tidy(contents: &str, order: SortOrder) -> Tidied {
+    let ending = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut tidied = Tidied::default();
+    let mut seen = std::collections::HashSet::new();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut group: Vec<&str> = Vec::new();
+
+    for line in contents.lines().map(str::trim) {
+        if is_pattern(line) {
+            // the first one always matches first, later copies do nothing
+            if seen.insert(line) {
+                group.push(line);
+            } else {
+                tidied.duplicates.push(line.to_string());
+            }
+            continue;
+        }
+        flush(&mut group, &mut lines, order, &mut tidied);
+        if line.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            tidied.blank_lines += 1;
+            continue;
+        }
+        lines.push(line);
+    }
+    flush(&mut group, &mut lines, order, &mut tidied);
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+        tidied.blank_lines += 1;
+    }
+
+    for line in lines {
+        tidied.contents.push_str(line);
+        tidied.contents.push_str(ending);
+    }
+    tidied
+}
+
+/// `contents` with `patterns` inserted into their alphabetical positions
+/// within the last group of patterns. `None` if that can't be done without
+/// changing what the file ignores: the group (or one of `patterns`) has
+/// negations, or `patterns` have comments and `#include`s
+pub fn insert_sorted(contents: &str, patterns: &[&str]) -> Option<String> {
+    let order = SortOrder::Alphabetical;
+    let negated = |p: &&str| syntax::split_modifiers(p).0.contains('!');
+    if patterns.iter().any(|p| !is_pattern(p) || negated(p)) {
+        return None;
+    }
+    let mut lines: Vec<&str> = contents.lines().collect();
+    let end = lines.iter().rposition(|l| is_pattern(l.trim()))? + 1;
+    let start = lines[..end]
+        .iter()
+        .rposition(|l| !is_pattern(l.trim()))
+        .map_or(0, |n| n + 1);
+    if lines[start..end].iter().any(|l| negated(&l.trim())) {
+        return None;
+    }
+
+    // each inserted pattern extends the group
+    for (end, pattern) in (end..).zip(patterns) {
+        let key = sort_key(pattern, order);
+        let at = lines[start..end]
+            .iter()
+            .position(|l| sort_key(l.trim(), order) > key)
+            .map_or(end, |n| start + n);
+        lines.insert(at, pattern);
+    }
+    let ending = if contents.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    Some(lines.iter().map(|l| format!("{l}{ending}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_with_negations_or_mixed_deletable_keep_their_order() {
+        let contents = "b\na\nb\n\n\n!d\nc\n// mixed\n(?d)f\ne\n#include x\nh\n(?i)g\n";
+        let tidied = tidy(contents, SortOrder::Alphabetical);
+        assert_eq!(
+            tidied.contents,
+            "a\nb\n\n!d\nc\n// mixed\n(?d)f\ne\n#include x\n(?i)g\nh\n"
+        );
+        assert_eq!(tidied.duplicates, ["b"]);
+        assert_eq!(
+            (tidied.sorted, tidied.unsortable, tidied.blank_lines),
+            (2, 2, 1)
+        );
+    }
+
+    #[test]
+    fn sorted_insert_gives_up_on_negations() {
+        assert_eq!(
+            insert_sorted("// x\na\nc\n", &["b"]).as_deref(),
+            Some("// x\na\nb\nc\n")
+        );
+        assert_eq!(insert_sorted("a\n!c\n", &["b"]), None);
+        assert_eq!(insert_sorted("a\n", &["!b"]), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! Windows paths in the form that syncthing and ignore patterns use.
+//!
+//! `canonicalize` returns verbatim paths (`\\?\C:\dir`, `\\?\UNC\server\share\dir`),
+//! which aren't equal to the usual form of the same path, and patterns always
+//! separate directories with `/`. On other systems paths don't have prefixes,
+//! so these functions only check that names are valid UTF-8.
+
+use std::{
+    ffi::OsStr,
+    path::{Component, Path, PathBuf, Prefix},
+};
+
+use anyhow::{Context, Result};
+
+/// `path` without the verbatim prefix: `\\?\C:\dir` becomes `C:\dir` and
+/// `\\?\UNC\server\share\dir` becomes `\\server\share\dir`
+pub fn simplify(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(disk) => format!("{}:", disk as char),
+            Prefix::VerbatimUNC(server, share) => format!(
+                r"\\{}\{}",
+                server.to_string_lossy(),
+                share.to_string_lossy()
+            ),
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+    let mut simple = PathBuf::from(prefix);
+    simple.extend(components);
+    simple
+}
+
+/// `canonicalize` that returns simplified paths
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    path.canonicalize().map(|p| simplify(&p))
+}
+
+/// `name` as UTF-8. Ignore files are UTF-8 text and syncthing doesn't sync
+/// other names, so patterns can't refer to them
+pub fn utf8(name: &OsStr) -> Result<&str> {
+    name.to_str().with_context(|| {
+        format!("{name:?} isn't valid UTF-8, syncthing can't sync it and patterns can't match it")
+    })
+}
+
+/// `path` as a part of an ignore pattern: drive letters and UNC prefixes are
+/// dropped, directories are separated by `/`
+pub fn to_pattern(path: &Path) -> Result<String> {
+    let mut pattern = String::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) => {}
+            Component::RootDir => pattern.push('/'),
+            component => {
+                if !pattern.is_empty() && !pattern.ends_with('/') {
+                    pattern.push('/');
+                }
+                pattern.push_str(utf8(component.as_os_str())?);
+            }
+        }
+    }
+    Ok(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn simplify_verbatim_disk() {
+        assert_eq!(
+            simplify(Path::new(r"\\?\C:\dir\file")),
+            PathBuf::from(r"C:\dir\file")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn simplify_verbatim_unc() {
+        assert_eq!(
+            simplify(Path::new(r"\\?\UNC\server\share\dir")),
+            PathBuf::from(r"\\server\share\dir")
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn simplify_keeps_usual_paths() {
+        for path in [r"C:\dir", r"\\server\share\dir", r"dir\file"] {
+            assert_eq!(simplify(Path::new(path)), PathBuf::from(path));
+        }
+    }
+
+    #[test]
+    fn simplify_keeps_plain_paths() {
+        for path in ["/dir/file", "dir/file", ""] {
+            assert_eq!(simplify(Path::new(path)), PathBuf::from(path));
+        }
+    }
+
+    #[test]
+    fn to_pattern_separates_with_slashes() {
+        assert_eq!(
+            to_pattern(Path::new("/dir/sub/file")).unwrap(),
+            "/dir/sub/file"
+        );
+        assert_eq!(to_pattern(Path::new("dir/file")).unwrap(), "dir/file");
+        assert_eq!(to_pattern(Path::new("/")).unwrap(), "/");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn to_pattern_drops_prefixes() {
+        assert_eq!(to_pattern(Path::new(r"C:\dir\file")).unwrap(), "/dir/file");
+        assert_eq!(to_pattern(Path::new(r"\\?\C:\dir")).unwrap(), "/dir");
+        assert_eq!(
+            to_pattern(Path::new(r"\\server\share\dir\file")).unwrap(),
+            "/dir/file"
+        );
+        assert_eq!(to_pattern(Path::new(r"dir\file")).unwrap(), "dir/file");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn to_pattern_rejects_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let name = OsStr::from_bytes(b"dir/\xff");
+        assert!(to_pattern(Path::new(name)).is_err());
+        assert!(utf8(name).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn to_pattern_rejects_non_utf8() {
+        use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+        // unpaired surrogate
+        let name = OsString::from_wide(&[b'd' as u16, 0xd800]);
+        assert!(to_pattern(Path::new(&name)).is_err());
+        assert!(utf8(&name).is_err());
+    }
+}